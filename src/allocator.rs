@@ -0,0 +1,295 @@
+// allocator.rs
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+use crate::memory::paging;
+
+pub mod bump;
+pub mod linked_list;
+pub mod buddy;
+pub mod slab;
+pub mod fixed_size_block;
+
+/* Kernel heap
+
+    A fixed virtual range is backed by fresh frames at boot and handed
+    to the global allocator, which makes `alloc` (Box, Vec, String...)
+    usable everywhere in the kernel after memory::init.
+*/
+pub const HEAP_START: u64 = 0x_4444_4444_0000;
+pub const HEAP_SIZE: usize = 1024 * 1024;
+
+/* Choosing a backend
+
+    Every backend is always built (and unit tested), the cargo feature
+    only picks which one backs the global allocator. Without any of
+    the features the fixed size block allocator is used.
+*/
+#[cfg(any(
+    all(feature = "bump_allocator", feature = "linked_list_allocator"),
+    all(feature = "bump_allocator", feature = "buddy_allocator"),
+    all(feature = "bump_allocator", feature = "slab_allocator"),
+    all(feature = "linked_list_allocator", feature = "buddy_allocator"),
+    all(feature = "linked_list_allocator", feature = "slab_allocator"),
+    all(feature = "buddy_allocator", feature = "slab_allocator"),
+))]
+compile_error!("only one allocator backend feature can be enabled");
+
+#[cfg(feature = "bump_allocator")]
+type Backend = bump::BumpAllocator;
+#[cfg(feature = "linked_list_allocator")]
+type Backend = linked_list::LinkedListAllocator;
+#[cfg(feature = "buddy_allocator")]
+type Backend = buddy::BuddyAllocator;
+#[cfg(feature = "slab_allocator")]
+type Backend = slab::SlabAllocator;
+#[cfg(not(any(
+    feature = "bump_allocator",
+    feature = "linked_list_allocator",
+    feature = "buddy_allocator",
+    feature = "slab_allocator",
+)))]
+type Backend = fixed_size_block::FixedSizeBlockAllocator;
+
+#[global_allocator]
+static ALLOCATOR: Locked<Backend> = Locked::new(Backend::new());
+
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+    let heap_start = VirtAddr::new(HEAP_START);
+    let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
+    let pages = Page::range_inclusive(
+        Page::<Size4KiB>::containing_address(heap_start),
+        Page::containing_address(heap_end),
+    );
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for page in pages {
+        paging::map_new(page, flags)?;
+    }
+
+    // Safe because the range was just mapped and nothing else uses it
+    interrupts::without_interrupts(|| unsafe { ALLOCATOR.init(HEAP_START as usize, HEAP_SIZE) });
+    Ok(())
+}
+
+// Free memory as a backend sees it, including space lost to rounding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FreeSpace {
+    pub total: usize,
+    // The biggest single allocation that could succeed right now
+    pub largest: usize,
+}
+
+/* Heap allocator backend
+
+    The backends only manage memory, locking and statistics are done
+    once for all of them by Locked.
+*/
+pub trait HeapAllocator {
+    fn name(&self) -> &'static str;
+
+    /// # Safety
+    /// The memory range has to be unused and valid for the lifetime of
+    /// the allocator, and this may only be called once.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    // Returns null when the request can't be satisfied
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+
+    /// # Safety
+    /// `ptr` has to come from `allocate` on this allocator with the same layout.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+
+    fn free_space(&self) -> FreeSpace;
+}
+
+/* Locked wrapper
+
+    GlobalAlloc only gets `&self`, so the allocators are kept behind a
+    spin::Mutex the same way vga_buffer::WRITER is. The lock is taken
+    with interrupts disabled so an allocation in an interrupt handler
+    can't spin forever on a lock the code it interrupted holds.
+
+    The counters are updated while the lock is held, so they always
+    agree with each other.
+*/
+pub struct Locked<A> {
+    inner: Mutex<A>,
+    heap_size: AtomicUsize,
+    in_use: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: Mutex::new(inner),
+            heap_size: AtomicUsize::new(0),
+            in_use: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+impl<A: HeapAllocator> Locked<A> {
+    /// # Safety
+    /// Same as HeapAllocator::init.
+    pub unsafe fn init(&self, heap_start: usize, heap_size: usize) {
+        let mut allocator = self.lock();
+        allocator.init(heap_start, heap_size);
+        self.heap_size.store(heap_size, Ordering::SeqCst);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        interrupts::without_interrupts(|| {
+            let allocator = self.lock();
+            HeapStats {
+                backend: allocator.name(),
+                heap_size: self.heap_size.load(Ordering::SeqCst),
+                in_use: self.in_use.load(Ordering::SeqCst),
+                peak: self.peak.load(Ordering::SeqCst),
+                allocations: self.allocations.load(Ordering::SeqCst),
+                deallocations: self.deallocations.load(Ordering::SeqCst),
+                failures: self.failures.load(Ordering::SeqCst),
+                free: allocator.free_space(),
+            }
+        })
+    }
+}
+
+unsafe impl<A: HeapAllocator> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| {
+            let mut allocator = self.lock();
+            let ptr = allocator.allocate(layout);
+            if ptr.is_null() {
+                self.failures.fetch_add(1, Ordering::SeqCst);
+            } else {
+                let in_use = self.in_use.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+                self.peak.fetch_max(in_use, Ordering::SeqCst);
+                self.allocations.fetch_add(1, Ordering::SeqCst);
+            }
+            ptr
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| {
+            let mut allocator = self.lock();
+            allocator.deallocate(ptr, layout);
+            self.in_use.fetch_sub(layout.size(), Ordering::SeqCst);
+            self.deallocations.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+}
+
+// A snapshot of the heap, printable with println!
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub backend: &'static str,
+    pub heap_size: usize,
+    // Bytes handed out, as requested by the callers
+    pub in_use: usize,
+    pub peak: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub failures: usize,
+    pub free: FreeSpace,
+}
+
+impl HeapStats {
+    // Percentage of free memory that isn't part of the largest free block
+    pub fn fragmentation(&self) -> usize {
+        if self.free.total == 0 {
+            return 0;
+        }
+        100 - self.free.largest * 100 / self.free.total
+    }
+
+    // Memory neither handed out nor free: block rounding and allocator bookkeeping
+    pub fn overhead(&self) -> usize {
+        self.heap_size.saturating_sub(self.in_use + self.free.total)
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "heap ({}): {} of {} bytes in use, peak {}",
+            self.backend, self.in_use, self.heap_size, self.peak)?;
+        writeln!(f, "  {} allocations, {} frees, {} failed",
+            self.allocations, self.deallocations, self.failures)?;
+        write!(f, "  {} bytes free, largest block {}, {}% fragmented, {} bytes overhead",
+            self.free.total, self.free.largest, self.fragmentation(), self.overhead())
+    }
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
+
+// Rounds `addr` up to `align`, which has to be a power of two
+pub fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+// Called by `alloc` when an allocation fails
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    crate::println!("out of memory: {} bytes (align {})", layout.size(), layout.align());
+    crate::println!("{}", stats());
+    panic!("allocation error: {:?}", layout)
+}
+
+// A small heap the backend unit tests can carve up, aligned so the buddy allocator gets one block
+#[cfg(test)]
+#[repr(align(16384))]
+pub struct TestHeap(pub [u8; 16384]);
+
+#[test_case]
+fn test_align_up() {
+    assert_eq!(align_up(0, 8), 0);
+    assert_eq!(align_up(1, 8), 8);
+    assert_eq!(align_up(8, 8), 8);
+    assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+}
+
+#[test_case]
+fn test_stats_track_usage() {
+    use alloc::boxed::Box;
+
+    let before = stats();
+    let value = Box::new([0u8; 100]);
+    let during = stats();
+    assert_eq!(during.in_use, before.in_use + 100);
+    assert!(during.peak >= during.in_use);
+    assert_eq!(during.allocations, before.allocations + 1);
+
+    drop(value);
+    let after = stats();
+    assert_eq!(after.in_use, before.in_use);
+    assert_eq!(after.deallocations, before.deallocations + 1);
+}
+
+#[test_case]
+fn test_fragmentation() {
+    let mut heap_stats = stats();
+    heap_stats.free = FreeSpace { total: 1000, largest: 1000 };
+    assert_eq!(heap_stats.fragmentation(), 0);
+    heap_stats.free = FreeSpace { total: 1000, largest: 250 };
+    assert_eq!(heap_stats.fragmentation(), 75);
+}
@@ -0,0 +1,212 @@
+// buddy.rs
+use core::alloc::Layout;
+use core::ptr;
+use super::{FreeSpace, HeapAllocator};
+
+/* Buddy allocator
+
+    Memory is handed out in power of two blocks, each aligned to its own
+    size. A block of order n is 2^n bytes, and splitting it gives two
+    "buddies" of order n - 1 whose addresses only differ in bit n - 1.
+    When a block is freed and its buddy is free too they merge back into
+    the bigger block, so the heap never fragments into tiny pieces for
+    long. The cost is internal fragmentation from rounding up.
+*/
+const MIN_ORDER: usize = 4;
+// 2^47 bytes, more than the whole lower half of the address space
+const ORDERS: usize = 48;
+
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+pub struct BuddyAllocator {
+    // One free list per order, below MIN_ORDER they stay empty
+    free_lists: [*mut FreeBlock; ORDERS],
+    heap_start: usize,
+    heap_end: usize,
+}
+
+// The free lists only point into the heap the allocator owns
+unsafe impl Send for BuddyAllocator {}
+
+impl BuddyAllocator {
+    pub const fn new() -> Self {
+        BuddyAllocator {
+            free_lists: [ptr::null_mut(); ORDERS],
+            heap_start: 0,
+            heap_end: 0,
+        }
+    }
+
+    // Smallest order big and aligned enough for the layout
+    fn order_for(layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align()).max(1 << MIN_ORDER);
+        let order = size.checked_next_power_of_two()?.trailing_zeros() as usize;
+        (order < ORDERS).then_some(order)
+    }
+
+    unsafe fn push(&mut self, order: usize, addr: usize) {
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock { next: self.free_lists[order] });
+        self.free_lists[order] = block;
+    }
+
+    fn pop(&mut self, order: usize) -> Option<usize> {
+        let block = self.free_lists[order];
+        if block.is_null() {
+            return None;
+        }
+        self.free_lists[order] = unsafe { (*block).next };
+        Some(block as usize)
+    }
+
+    // Takes a specific block off a free list, returning false if it isn't there
+    fn remove(&mut self, order: usize, addr: usize) -> bool {
+        let mut link: *mut *mut FreeBlock = &mut self.free_lists[order];
+        unsafe {
+            while !(*link).is_null() {
+                if *link as usize == addr {
+                    *link = (**link).next;
+                    return true;
+                }
+                link = &mut (**link).next;
+            }
+        }
+        false
+    }
+}
+
+impl HeapAllocator for BuddyAllocator {
+    fn name(&self) -> &'static str {
+        "buddy"
+    }
+
+    // The heap doesn't have to be a power of two, it's cut into the biggest aligned blocks that fit
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        let min_block = 1 << MIN_ORDER;
+        let mut addr = (heap_start + min_block - 1) & !(min_block - 1);
+        let end = (heap_start + heap_size) & !(min_block - 1);
+        self.heap_start = addr;
+        self.heap_end = end;
+
+        while addr < end {
+            let mut order = (addr.trailing_zeros() as usize).min(ORDERS - 1);
+            while addr + (1 << order) > end {
+                order -= 1;
+            }
+            self.push(order, addr);
+            addr += 1 << order;
+        }
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let order = match Self::order_for(&layout) {
+            Some(order) => order,
+            None => return ptr::null_mut(),
+        };
+
+        let found = (order..ORDERS).find(|&larger| !self.free_lists[larger].is_null());
+        let mut current = match found {
+            Some(larger) => larger,
+            None => return ptr::null_mut(),
+        };
+
+        let block = self.pop(current).unwrap();
+        // Split down to the size we need, freeing the upper halves
+        while current > order {
+            current -= 1;
+            unsafe { self.push(current, block + (1 << current)) };
+        }
+        block as *mut u8
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let mut order = Self::order_for(&layout).unwrap();
+        let mut block = ptr as usize;
+
+        // Merge with the buddy for as long as it is free
+        while order < ORDERS - 1 {
+            let buddy = block ^ (1 << order);
+            if buddy < self.heap_start || buddy + (1 << order) > self.heap_end {
+                break;
+            }
+            if !self.remove(order, buddy) {
+                break;
+            }
+            block = block.min(buddy);
+            order += 1;
+        }
+        self.push(order, block);
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        let mut free = FreeSpace::default();
+        for (order, &head) in self.free_lists.iter().enumerate() {
+            let mut block = head;
+            while !block.is_null() {
+                free.total += 1 << order;
+                free.largest = 1 << order;
+                block = unsafe { (*block).next };
+            }
+        }
+        free
+    }
+}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        BuddyAllocator::new()
+    }
+}
+
+#[cfg(test)]
+use super::TestHeap;
+
+#[test_case]
+fn test_buddies_split_and_merge() {
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = BuddyAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+    assert_eq!(allocator.free_space(), FreeSpace { total: 16384, largest: 16384 });
+
+    // 100 bytes rounds up to a 128 byte block, splitting the whole heap on the way
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let first = allocator.allocate(layout);
+    let second = allocator.allocate(layout);
+    assert_eq!(first as usize, heap.0.as_ptr() as usize);
+    assert_eq!(second as usize, first as usize + 128);
+    assert_eq!(allocator.free_space().total, 16384 - 256);
+    assert_eq!(allocator.free_space().largest, 8192);
+
+    unsafe {
+        allocator.deallocate(second, layout);
+        allocator.deallocate(first, layout);
+    }
+    assert_eq!(allocator.free_space(), FreeSpace { total: 16384, largest: 16384 });
+}
+
+#[test_case]
+fn test_buddy_alignment_and_exhaustion() {
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = BuddyAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+    let small = allocator.allocate(Layout::from_size_align(16, 16).unwrap());
+    let aligned = allocator.allocate(Layout::from_size_align(64, 1024).unwrap());
+    assert_eq!(aligned as usize % 1024, 0);
+    assert_ne!(small, aligned);
+
+    // Only half of the heap is left in one piece
+    assert!(allocator.allocate(Layout::from_size_align(16384, 8).unwrap()).is_null());
+    assert!(!allocator.allocate(Layout::from_size_align(8192, 8).unwrap()).is_null());
+}
+
+#[test_case]
+fn test_uneven_heap() {
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = BuddyAllocator::new();
+    // 12 KiB becomes an 8 KiB and a 4 KiB block
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, 12288) };
+    assert_eq!(allocator.free_space(), FreeSpace { total: 12288, largest: 8192 });
+}
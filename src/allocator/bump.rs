@@ -0,0 +1,92 @@
+// bump.rs
+use core::alloc::Layout;
+use core::ptr;
+use super::{align_up, FreeSpace, HeapAllocator};
+
+/* Bump allocator
+
+    Allocating just moves a pointer forward, which makes it the fastest
+    backend and a baseline for the others. Memory is only reclaimed once
+    every allocation has been freed, so a single long lived allocation
+    keeps the whole heap from being reused.
+*/
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl BumpAllocator {
+    pub const fn new() -> Self {
+        BumpAllocator {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+}
+
+impl HeapAllocator for BumpAllocator {
+    fn name(&self) -> &'static str {
+        "bump"
+    }
+
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) if end <= self.heap_end => end,
+            _ => return ptr::null_mut(),
+        };
+
+        self.next = alloc_end;
+        self.allocations += 1;
+        alloc_start as *mut u8
+    }
+
+    unsafe fn deallocate(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        let free = self.heap_end - self.next;
+        FreeSpace { total: free, largest: free }
+    }
+}
+
+impl Default for BumpAllocator {
+    fn default() -> Self {
+        BumpAllocator::new()
+    }
+}
+
+#[test_case]
+fn test_bump_resets_when_empty() {
+    use super::TestHeap;
+
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = BumpAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let first = allocator.allocate(layout);
+    let second = allocator.allocate(layout);
+    assert_eq!(second as usize, first as usize + 104);
+
+    // Memory only comes back once everything is freed
+    unsafe { allocator.deallocate(first, layout) };
+    assert_eq!(allocator.free_space().total, 16384 - 204);
+    unsafe { allocator.deallocate(second, layout) };
+    assert_eq!(allocator.free_space().total, 16384);
+    assert_eq!(allocator.allocate(layout), first);
+}
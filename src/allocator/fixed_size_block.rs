@@ -0,0 +1,126 @@
+// fixed_size_block.rs
+use core::alloc::Layout;
+use core::mem;
+use core::ptr;
+use super::linked_list::LinkedListAllocator;
+use super::{FreeSpace, HeapAllocator};
+
+/* Fixed size block allocator
+
+    Small allocations are rounded up to one of a few block sizes and
+    served from a free list per size, which is just a pop or a push.
+    Blocks are never split or merged, so freed blocks go back on their
+    list for reuse. Anything bigger than the largest block, and any new
+    blocks, come from the linked list allocator underneath.
+*/
+// Block sizes double as their alignment, so they all have to be powers of two
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback: LinkedListAllocator,
+}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback: LinkedListAllocator::new(),
+        }
+    }
+
+    // Index of the smallest block size that fits the layout
+    fn list_index(layout: &Layout) -> Option<usize> {
+        let required = layout.size().max(layout.align());
+        BLOCK_SIZES.iter().position(|&size| size >= required)
+    }
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    fn name(&self) -> &'static str {
+        "fixed size block"
+    }
+
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback.init(heap_start, heap_size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        match Self::list_index(&layout) {
+            Some(index) => match self.list_heads[index].take() {
+                Some(node) => {
+                    self.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                None => {
+                    let block_size = BLOCK_SIZES[index];
+                    let block_layout = Layout::from_size_align(block_size, block_size).unwrap();
+                    self.fallback.allocate(block_layout)
+                }
+            },
+            None => self.fallback.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match Self::list_index(&layout) {
+            Some(index) => {
+                // Every block size is big and aligned enough to hold a node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+
+                let node = ListNode { next: self.list_heads[index].take() };
+                let node_ptr = ptr as *mut ListNode;
+                ptr::write(node_ptr, node);
+                self.list_heads[index] = Some(&mut *node_ptr);
+            }
+            None => self.fallback.deallocate(ptr, layout),
+        }
+    }
+
+    // Blocks sitting on the free lists count as free, but only for their own size class
+    fn free_space(&self) -> FreeSpace {
+        let mut free = self.fallback.free_space();
+        for (head, &block_size) in self.list_heads.iter().zip(BLOCK_SIZES) {
+            let mut current = head;
+            while let Some(node) = current {
+                free.total += block_size;
+                free.largest = free.largest.max(block_size);
+                current = &node.next;
+            }
+        }
+        free
+    }
+}
+
+impl Default for FixedSizeBlockAllocator {
+    fn default() -> Self {
+        FixedSizeBlockAllocator::new()
+    }
+}
+
+#[test_case]
+fn test_blocks_are_reused() {
+    use super::TestHeap;
+
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = FixedSizeBlockAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+    let small = Layout::from_size_align(12, 4).unwrap();
+    let first = allocator.allocate(small);
+    assert_eq!(first as usize % 16, 0);
+    unsafe { allocator.deallocate(first, small) };
+    // Same size class, so the freed block comes straight back
+    assert_eq!(allocator.allocate(Layout::from_size_align(16, 8).unwrap()), first);
+
+    // Too big for any block, served by the fallback
+    let large = Layout::from_size_align(4096, 8).unwrap();
+    assert!(!allocator.allocate(large).is_null());
+    assert!(allocator.free_space().total < 16384 - 4096);
+}
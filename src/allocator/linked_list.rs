@@ -0,0 +1,221 @@
+// linked_list.rs
+use core::alloc::Layout;
+use core::mem;
+use core::ptr;
+use super::{align_up, FreeSpace, HeapAllocator};
+
+/* Linked list allocator
+
+    Free memory is kept as a list of regions sorted by address, with
+    the list node stored at the start of the region itself. Allocation
+    takes the first region that fits and hands any leftover back to the
+    list, freeing inserts the region in order and merges it with its
+    neighbours so the heap doesn't fragment into small pieces.
+*/
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut ListNode>,
+}
+
+impl ListNode {
+    const fn new(size: usize) -> Self {
+        ListNode { size, next: None }
+    }
+
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+pub struct LinkedListAllocator {
+    head: ListNode,
+}
+
+impl LinkedListAllocator {
+    pub const fn new() -> Self {
+        LinkedListAllocator { head: ListNode::new(0) }
+    }
+
+    // Smallest region that can hold a list node once it's freed again
+    pub fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        (layout.size().max(mem::size_of::<ListNode>()), layout.align())
+    }
+
+    // Inserts the region in address order and merges it with adjacent free regions
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        let mut current = &mut self.head;
+        while let Some(ref next) = current.next {
+            if next.start_addr() >= addr {
+                break;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+
+        let mut node = ListNode::new(size);
+        node.next = current.next.take();
+        let node_ptr = addr as *mut ListNode;
+        ptr::write(node_ptr, node);
+        let node = &mut *node_ptr;
+
+        // Merge with the following region
+        if let Some(next) = node.next.take() {
+            if node.end_addr() == next.start_addr() {
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
+
+        // Merge into the preceding region, the head is a dummy with size 0
+        if current.size > 0 && current.end_addr() == node.start_addr() {
+            current.size += node.size;
+            current.next = node.next.take();
+        } else {
+            current.next = Some(node);
+        }
+    }
+
+    // Unlinks the first region that fits, returning it with the allocation's start address
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+        while let Some(ref mut region) = current.next {
+            if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
+                let next = region.next.take();
+                let found = Some((current.next.take().unwrap(), alloc_start));
+                current.next = next;
+                return found;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+        None
+    }
+
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
+        let alloc_start = align_up(region.start_addr(), align);
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+        if alloc_end > region.end_addr() {
+            return Err(());
+        }
+
+        // Whatever is left over has to be big enough to be a free region itself
+        let excess = region.end_addr() - alloc_end;
+        if excess > 0 && excess < mem::size_of::<ListNode>() {
+            return Err(());
+        }
+        Ok(alloc_start)
+    }
+}
+
+impl HeapAllocator for LinkedListAllocator {
+    fn name(&self) -> &'static str {
+        "linked list"
+    }
+
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+        match self.find_region(size, align) {
+            Some((region, alloc_start)) => {
+                let alloc_end = alloc_start + size;
+                let region_start = region.start_addr();
+                let region_end = region.end_addr();
+                // Padding in front of an aligned allocation goes back on the list
+                if alloc_start > region_start {
+                    unsafe { self.add_free_region(region_start, alloc_start - region_start) };
+                }
+                if region_end > alloc_end {
+                    unsafe { self.add_free_region(alloc_end, region_end - alloc_end) };
+                }
+                alloc_start as *mut u8
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.add_free_region(ptr as usize, size);
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        let mut free = FreeSpace::default();
+        let mut current = &self.head.next;
+        while let Some(region) = current {
+            free.total += region.size;
+            free.largest = free.largest.max(region.size);
+            current = &region.next;
+        }
+        free
+    }
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        LinkedListAllocator::new()
+    }
+}
+
+#[cfg(test)]
+use super::TestHeap;
+
+#[test_case]
+fn test_allocate_until_full() {
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, 4096) };
+
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    for _ in 0..4 {
+        assert!(!allocator.allocate(layout).is_null());
+    }
+    assert!(allocator.allocate(layout).is_null());
+    assert_eq!(allocator.free_space(), FreeSpace { total: 0, largest: 0 });
+}
+
+#[test_case]
+fn test_freed_regions_coalesce() {
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, 4096) };
+
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let blocks = [
+        allocator.allocate(layout),
+        allocator.allocate(layout),
+        allocator.allocate(layout),
+    ];
+    // Freed out of order, they still merge back into one region
+    unsafe {
+        allocator.deallocate(blocks[0], layout);
+        allocator.deallocate(blocks[2], layout);
+        allocator.deallocate(blocks[1], layout);
+    }
+    assert_eq!(allocator.free_space(), FreeSpace { total: 4096, largest: 4096 });
+    assert!(!allocator.allocate(Layout::from_size_align(4096, 8).unwrap()).is_null());
+}
+
+#[test_case]
+fn test_aligned_allocation() {
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, 4096) };
+
+    allocator.allocate(Layout::from_size_align(24, 8).unwrap());
+    let aligned = allocator.allocate(Layout::from_size_align(64, 256).unwrap());
+    assert_eq!(aligned as usize % 256, 0);
+}
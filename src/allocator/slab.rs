@@ -0,0 +1,228 @@
+// slab.rs
+use core::alloc::Layout;
+use core::mem;
+use core::ptr;
+use super::linked_list::LinkedListAllocator;
+use super::{align_up, FreeSpace, HeapAllocator};
+
+/* Slab allocator
+
+    Each size class gets its own 4 KiB slabs, cut into equal objects.
+    A small header at the start of the slab keeps the slab's own free
+    list, and because slabs are aligned to their size the header of any
+    object is found by rounding its address down.
+
+    Only slabs with a free object are on a class's list, so allocation
+    never has to search. Unlike the fixed size block allocator, a slab
+    that becomes completely free is handed back to the linked list
+    allocator underneath (unless it's the last one for its class), so
+    memory can move between size classes.
+*/
+const SLAB_SIZE: usize = 4096;
+const CLASS_SIZES: &[usize] = &[16, 32, 64, 128, 256, 512, 1024];
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+struct SlabHeader {
+    next: *mut SlabHeader,
+    free: *mut FreeObject,
+    free_count: usize,
+    capacity: usize,
+}
+
+pub struct SlabAllocator {
+    // Slabs with at least one free object, per class
+    partial: [*mut SlabHeader; CLASS_SIZES.len()],
+    fallback: LinkedListAllocator,
+}
+
+// The slab lists only point into the heap the allocator owns
+unsafe impl Send for SlabAllocator {}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        SlabAllocator {
+            partial: [ptr::null_mut(); CLASS_SIZES.len()],
+            fallback: LinkedListAllocator::new(),
+        }
+    }
+
+    fn class_index(layout: &Layout) -> Option<usize> {
+        let required = layout.size().max(layout.align());
+        CLASS_SIZES.iter().position(|&size| size >= required)
+    }
+
+    fn slab_layout() -> Layout {
+        Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+    }
+
+    // Gets a slab from the fallback and threads all of its objects onto its free list
+    fn new_slab(&mut self, class: usize) -> *mut SlabHeader {
+        let slab = self.fallback.allocate(Self::slab_layout()) as *mut SlabHeader;
+        if slab.is_null() {
+            return slab;
+        }
+
+        let object_size = CLASS_SIZES[class];
+        let first = align_up(mem::size_of::<SlabHeader>(), object_size);
+        let mut free: *mut FreeObject = ptr::null_mut();
+        // Built back to front so objects are handed out in address order
+        for offset in (first..SLAB_SIZE).step_by(object_size).rev() {
+            let object = (slab as usize + offset) as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free }) };
+            free = object;
+        }
+
+        let capacity = (SLAB_SIZE - first) / object_size;
+        unsafe {
+            slab.write(SlabHeader {
+                next: self.partial[class],
+                free,
+                free_count: capacity,
+                capacity,
+            })
+        };
+        self.partial[class] = slab;
+        slab
+    }
+
+    // Unlinks a slab from anywhere in a class's list
+    fn unlink(&mut self, class: usize, slab: *mut SlabHeader) {
+        let mut link: *mut *mut SlabHeader = &mut self.partial[class];
+        unsafe {
+            while !(*link).is_null() {
+                if *link == slab {
+                    *link = (*slab).next;
+                    return;
+                }
+                link = &mut (**link).next;
+            }
+        }
+    }
+}
+
+impl HeapAllocator for SlabAllocator {
+    fn name(&self) -> &'static str {
+        "slab"
+    }
+
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback.init(heap_start, heap_size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let class = match Self::class_index(&layout) {
+            Some(class) => class,
+            None => return self.fallback.allocate(layout),
+        };
+
+        let mut slab = self.partial[class];
+        if slab.is_null() {
+            slab = self.new_slab(class);
+            if slab.is_null() {
+                return ptr::null_mut();
+            }
+        }
+
+        unsafe {
+            let object = (*slab).free;
+            (*slab).free = (*object).next;
+            (*slab).free_count -= 1;
+            // A full slab leaves the list, it's always the head here
+            if (*slab).free_count == 0 {
+                self.partial[class] = (*slab).next;
+            }
+            object as *mut u8
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let class = match Self::class_index(&layout) {
+            Some(class) => class,
+            None => return self.fallback.deallocate(ptr, layout),
+        };
+
+        let slab = (ptr as usize & !(SLAB_SIZE - 1)) as *mut SlabHeader;
+        let object = ptr as *mut FreeObject;
+        object.write(FreeObject { next: (*slab).free });
+        (*slab).free = object;
+        (*slab).free_count += 1;
+
+        if (*slab).free_count == 1 {
+            (*slab).next = self.partial[class];
+            self.partial[class] = slab;
+        } else if (*slab).free_count == (*slab).capacity
+            && !(self.partial[class] == slab && (*slab).next.is_null())
+        {
+            self.unlink(class, slab);
+            self.fallback.deallocate(slab as *mut u8, Self::slab_layout());
+        }
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        let mut free = self.fallback.free_space();
+        for (&head, &object_size) in self.partial.iter().zip(CLASS_SIZES) {
+            let mut slab = head;
+            while !slab.is_null() {
+                unsafe {
+                    free.total += (*slab).free_count * object_size;
+                    free.largest = free.largest.max(object_size);
+                    slab = (*slab).next;
+                }
+            }
+        }
+        free
+    }
+}
+
+impl Default for SlabAllocator {
+    fn default() -> Self {
+        SlabAllocator::new()
+    }
+}
+
+#[cfg(test)]
+use super::TestHeap;
+
+#[test_case]
+fn test_objects_share_a_slab() {
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = SlabAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let first = allocator.allocate(layout);
+    let second = allocator.allocate(layout);
+    // Both come from the same slab, one 32 byte object apart
+    assert_eq!(first as usize & !(SLAB_SIZE - 1), second as usize & !(SLAB_SIZE - 1));
+    assert_eq!(second as usize, first as usize + 32);
+
+    unsafe { allocator.deallocate(first, layout) };
+    assert_eq!(allocator.allocate(layout), first);
+}
+
+#[test_case]
+fn test_empty_slabs_are_released() {
+    let mut heap = TestHeap([0; 16384]);
+    let mut allocator = SlabAllocator::new();
+    unsafe { allocator.init(heap.0.as_mut_ptr() as usize, heap.0.len()) };
+
+    // 1024 byte objects fit three to a slab, so four need two slabs
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let objects = [
+        allocator.allocate(layout),
+        allocator.allocate(layout),
+        allocator.allocate(layout),
+        allocator.allocate(layout),
+    ];
+    assert!(objects.iter().all(|object| !object.is_null()));
+    assert_eq!(allocator.free_space().largest, 16384 - 2 * SLAB_SIZE);
+
+    for object in objects {
+        unsafe { allocator.deallocate(object, layout) };
+    }
+    // One empty slab is kept for the class, the other went back
+    assert_eq!(allocator.free_space().total, 16384 - SLAB_SIZE + 3 * 1024);
+}
@@ -0,0 +1,76 @@
+// gdt.rs
+use x86_64::VirtAddr;
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use lazy_static::lazy_static;
+use core::ptr::addr_of;
+
+/* Interrupt Stack Table indices
+
+    The TSS holds a table of 7 known-good stacks. When an IDT entry
+    names one of these indices the CPU switches to that stack before
+    pushing the interrupt frame, so the handler still works even if the
+    kernel stack has overflowed into the guard page.
+*/
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const STACK_SIZE: usize = 4096 * 5;
+
+// Returns the top of the stack as x86 stacks grow downwards
+macro_rules! interrupt_stack {
+    () => {{
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(addr_of!(STACK));
+        stack_start + STACK_SIZE
+    }};
+}
+
+lazy_static! {
+    static ref TSS: TaskStateSegment = {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = interrupt_stack!();
+        tss.interrupt_stack_table[NMI_IST_INDEX as usize] = interrupt_stack!();
+        tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] = interrupt_stack!();
+        tss
+    };
+}
+
+struct Selectors {
+    code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    tss_selector: SegmentSelector,
+}
+
+/* Global Descriptor Table
+
+    In 64-bit mode segmentation is mostly disabled, but the GDT is
+    still needed to switch between kernel and user mode and to load
+    the TSS. We replace whatever table the bootloader left behind.
+*/
+lazy_static! {
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        (gdt, Selectors { code_selector, data_selector, tss_selector })
+    };
+}
+
+pub fn init() {
+    use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+    use x86_64::instructions::tables::load_tss;
+
+    GDT.0.load();
+    // The old segment registers still point into the bootloader's GDT
+    unsafe {
+        CS::set_reg(GDT.1.code_selector);
+        SS::set_reg(GDT.1.data_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
+        load_tss(GDT.1.tss_selector);
+    }
+}
@@ -0,0 +1,183 @@
+// interrupts.rs
+use x86_64::structures::idt::{
+    HandlerFunc, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+};
+use spin::Mutex;
+use lazy_static::lazy_static;
+use crate::{gdt, println};
+
+/* Interrupt Descriptor Table
+
+    The IDT tells the CPU which handler to run for each of the 256 interrupt
+    vectors. Vectors 0-31 are reserved for CPU exceptions, everything from
+    32 upwards is free to be used by hardware and software interrupts.
+
+    The table sits behind a Mutex so kernel code can register handlers for
+    the free vectors after the table has been loaded. The lazy_static storage
+    never moves, so the CPU keeps seeing our changes without reloading it.
+*/
+lazy_static! {
+    static ref IDT: Mutex<InterruptDescriptorTable> = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.debug.set_handler_fn(debug_handler);
+        // Switch to known-good stacks for faults that may hit a broken kernel stack
+        unsafe {
+            idt.non_maskable_interrupt
+                .set_handler_fn(non_maskable_interrupt_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            idt.machine_check
+                .set_handler_fn(machine_check_handler)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        }
+        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.overflow.set_handler_fn(overflow_handler);
+        idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.device_not_available.set_handler_fn(device_not_available_handler);
+        // Vector 9 is legacy and only reachable by index
+        idt[9].set_handler_fn(coprocessor_segment_overrun_handler);
+        idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+        idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+        idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+        idt.alignment_check.set_handler_fn(alignment_check_handler);
+        idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+        idt.virtualization.set_handler_fn(virtualization_handler);
+        idt.cp_protection_exception.set_handler_fn(cp_protection_handler);
+        idt.hv_injection_exception.set_handler_fn(hv_injection_handler);
+        idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+        idt.security_exception.set_handler_fn(security_exception_handler);
+        Mutex::new(idt)
+    };
+}
+
+// The first vector that isnt reserved for CPU exceptions
+pub const FIRST_FREE_VECTOR: u8 = 32;
+
+pub fn init_idt() {
+    let idt = IDT.lock();
+    // Safe because the table lives in a static and is never moved or dropped
+    unsafe { idt.load_unsafe() };
+}
+
+/* Registers a handler for one of the free vectors (32-255).
+   Exception vectors have fixed handlers and cannot be replaced.
+*/
+pub fn set_handler(vector: u8, handler: HandlerFunc) {
+    assert!(
+        vector >= FIRST_FREE_VECTOR,
+        "vector {} is reserved for CPU exceptions", vector
+    );
+    IDT.lock()[vector as usize].set_handler_fn(handler);
+}
+
+/* Exception handlers
+
+    Recoverable exceptions (debug, breakpoint) just report and return.
+    Everything else would re-run the faulting instruction on return, so
+    the handler reports the fault and then panics. Since those never
+    return, they can safely take over the consoles first.
+*/
+fn report(name: &str, error_code: Option<u64>, stack_frame: &InterruptStackFrame) {
+    println!("EXCEPTION: {}", name);
+    if let Some(code) = error_code {
+        println!("Error Code: {:#x}", code);
+    }
+    println!("{:#?}", stack_frame);
+}
+
+// Generates a handler that reports the exception and halts the kernel
+macro_rules! fatal_handler {
+    ($handler:ident, $name:expr) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame) {
+            crate::unlock_consoles();
+            report($name, None, &stack_frame);
+            panic!("unrecoverable exception: {}", $name);
+        }
+    };
+    ($handler:ident, $name:expr, error_code) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            crate::unlock_consoles();
+            report($name, Some(error_code), &stack_frame);
+            panic!("unrecoverable exception: {}", $name);
+        }
+    };
+}
+
+fatal_handler!(divide_error_handler, "DIVIDE ERROR");
+fatal_handler!(non_maskable_interrupt_handler, "NON-MASKABLE INTERRUPT");
+fatal_handler!(overflow_handler, "OVERFLOW");
+fatal_handler!(bound_range_exceeded_handler, "BOUND RANGE EXCEEDED");
+fatal_handler!(invalid_opcode_handler, "INVALID OPCODE");
+fatal_handler!(device_not_available_handler, "DEVICE NOT AVAILABLE");
+fatal_handler!(coprocessor_segment_overrun_handler, "COPROCESSOR SEGMENT OVERRUN");
+fatal_handler!(invalid_tss_handler, "INVALID TSS", error_code);
+fatal_handler!(segment_not_present_handler, "SEGMENT NOT PRESENT", error_code);
+fatal_handler!(stack_segment_fault_handler, "STACK SEGMENT FAULT", error_code);
+fatal_handler!(general_protection_fault_handler, "GENERAL PROTECTION FAULT", error_code);
+fatal_handler!(x87_floating_point_handler, "x87 FLOATING POINT");
+fatal_handler!(alignment_check_handler, "ALIGNMENT CHECK", error_code);
+fatal_handler!(simd_floating_point_handler, "SIMD FLOATING POINT");
+fatal_handler!(virtualization_handler, "VIRTUALIZATION");
+fatal_handler!(cp_protection_handler, "CONTROL PROTECTION", error_code);
+fatal_handler!(hv_injection_handler, "HYPERVISOR INJECTION");
+fatal_handler!(vmm_communication_handler, "VMM COMMUNICATION", error_code);
+fatal_handler!(security_exception_handler, "SECURITY EXCEPTION", error_code);
+
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    report("DEBUG", None, &stack_frame);
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    report("BREAKPOINT", None, &stack_frame);
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    crate::unlock_consoles();
+    report("PAGE FAULT", Some(error_code.bits()), &stack_frame);
+    // CR2 holds the virtual address that caused the fault
+    println!("Accessed Address: {:?}", Cr2::read());
+    println!("{:?}", error_code);
+    panic!("unrecoverable exception: PAGE FAULT");
+}
+
+// Double faults and machine checks must never return
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) -> ! {
+    crate::unlock_consoles();
+    report("DOUBLE FAULT", Some(error_code), &stack_frame);
+    panic!("unrecoverable exception: DOUBLE FAULT");
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    crate::unlock_consoles();
+    report("MACHINE CHECK", None, &stack_frame);
+    panic!("unrecoverable exception: MACHINE CHECK");
+}
+
+#[test_case]
+fn test_breakpoint_exception() {
+    // Execution should continue after the breakpoint handler returns
+    x86_64::instructions::interrupts::int3();
+}
+
+crate::kernel_test! {
+    #[should_panic]
+    fn set_handler_rejects_exception_vectors() {
+        extern "x86-interrupt" fn handler(_stack_frame: InterruptStackFrame) {}
+        set_handler(3, handler);
+    }
+}
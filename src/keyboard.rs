@@ -0,0 +1,409 @@
+// keyboard.rs
+use x86_64::instructions::port::Port;
+use x86_64::instructions::interrupts::without_interrupts;
+use spin::Mutex;
+use crate::pic;
+use crate::vga_buffer;
+
+pub mod scancodes;
+pub mod layouts;
+mod queue;
+
+pub use scancodes::{Decoder, KeyCode, KeyEvent, KeyState, ScancodeSet};
+pub use layouts::{De105Key, KeyboardLayout, Uk105Key, Us104Key};
+use queue::KeyQueue;
+
+// What a key press means once the layout and modifiers have been applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodedKey {
+    Unicode(char),
+    RawKey(KeyCode),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub lshift: bool,
+    pub rshift: bool,
+    pub lctrl: bool,
+    pub rctrl: bool,
+    pub lalt: bool,
+    // Right Alt is AltGr on international layouts
+    pub ralt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+/* Constructing the Set LEDs command byte
+
+    Bits | Value
+    0    | Scroll Lock
+    1    | Num Lock
+    2    | Caps Lock
+
+*/
+impl Modifiers {
+    pub const fn new() -> Modifiers {
+        Modifiers {
+            lshift: false,
+            rshift: false,
+            lctrl: false,
+            rctrl: false,
+            lalt: false,
+            ralt: false,
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+        }
+    }
+
+    pub fn is_shifted(&self) -> bool {
+        self.lshift || self.rshift
+    }
+
+    pub fn is_ctrl(&self) -> bool {
+        self.lctrl || self.rctrl
+    }
+
+    // Right Alt doesn't count, it is AltGr on international layouts
+    pub fn is_alt(&self) -> bool {
+        self.lalt
+    }
+
+    // Ctrl+Alt is the usual stand-in for AltGr
+    pub fn is_altgr(&self) -> bool {
+        self.ralt || (self.is_ctrl() && self.lalt)
+    }
+
+    // Whether letters come out in upper case
+    pub fn is_caps(&self) -> bool {
+        self.is_shifted() ^ self.caps_lock
+    }
+
+    fn leds(&self) -> u8 {
+        (self.caps_lock as u8) << 2 | (self.num_lock as u8) << 1 | self.scroll_lock as u8
+    }
+
+    // One bit per field, in declaration order, so the state fits next to a key in the queue
+    fn bits(&self) -> u16 {
+        [
+            self.lshift, self.rshift, self.lctrl, self.rctrl, self.lalt, self.ralt,
+            self.caps_lock, self.num_lock, self.scroll_lock,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (bit, &set)| bits | (set as u16) << bit)
+    }
+
+    fn from_bits(bits: u16) -> Modifiers {
+        let bit = |index: u16| bits & 1 << index != 0;
+        Modifiers {
+            lshift: bit(0),
+            rshift: bit(1),
+            lctrl: bit(2),
+            rctrl: bit(3),
+            lalt: bit(4),
+            ralt: bit(5),
+            caps_lock: bit(6),
+            num_lock: bit(7),
+            scroll_lock: bit(8),
+        }
+    }
+}
+
+/* Keyboard state machine
+
+    Turns key events into characters by tracking which modifiers are
+    held and which lock keys are on, then asking the layout what the
+    key means. Modifier and lock keys don't produce anything themselves.
+*/
+pub struct Keyboard {
+    decoder: Decoder,
+    modifiers: Modifiers,
+    layout: &'static dyn KeyboardLayout,
+}
+
+impl Keyboard {
+    pub const fn new(set: ScancodeSet, layout: &'static dyn KeyboardLayout) -> Keyboard {
+        Keyboard {
+            decoder: Decoder::new(set),
+            modifiers: Modifiers::new(),
+            layout,
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn layout(&self) -> &'static dyn KeyboardLayout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: &'static dyn KeyboardLayout) {
+        self.layout = layout;
+    }
+
+    pub fn scancode_set(&self) -> ScancodeSet {
+        self.decoder.set()
+    }
+
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        self.decoder.set_scancode_set(set);
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Option<KeyEvent> {
+        self.decoder.advance(byte)
+    }
+
+    pub fn process_event(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        let down = event.state == KeyState::Down;
+        let modifiers = &mut self.modifiers;
+
+        match event.code {
+            KeyCode::ShiftLeft => modifiers.lshift = down,
+            KeyCode::ShiftRight => modifiers.rshift = down,
+            KeyCode::ControlLeft => modifiers.lctrl = down,
+            KeyCode::ControlRight => modifiers.rctrl = down,
+            KeyCode::AltLeft => modifiers.lalt = down,
+            KeyCode::AltRight => modifiers.ralt = down,
+            KeyCode::CapsLock if down => modifiers.caps_lock = !modifiers.caps_lock,
+            KeyCode::NumLock if down => modifiers.num_lock = !modifiers.num_lock,
+            KeyCode::ScrollLock if down => modifiers.scroll_lock = !modifiers.scroll_lock,
+            KeyCode::CapsLock | KeyCode::NumLock | KeyCode::ScrollLock => {}
+            code if down => return Some(self.layout.map_keycode(code, modifiers)),
+            _ => {}
+        }
+        None
+    }
+}
+
+/* PS/2 controller
+
+    Port 0x60 carries bytes to and from the keyboard, bit 1 of the
+    status port 0x64 is set while the controller is still busy with
+    the last byte we sent it.
+*/
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+// Commands and replies on the keyboard wire
+const COMMAND_SET_LEDS: u8 = 0xED;
+const REPLY_ACK: u8 = 0xFA;
+const REPLY_RESEND: u8 = 0xFE;
+const REPLY_SELF_TEST_PASSED: u8 = 0xAA;
+const REPLY_ECHO: u8 = 0xEE;
+const REPLY_ERROR: u8 = 0x00;
+const REPLY_OVERRUN: u8 = 0xFF;
+
+struct Driver {
+    keyboard: Keyboard,
+    data: Port<u8>,
+    status: Port<u8>,
+    // LED byte waiting for the keyboard to acknowledge the Set LEDs command
+    pending_leds: Option<u8>,
+}
+
+impl Driver {
+    const fn new() -> Driver {
+        Driver {
+            keyboard: Keyboard::new(ScancodeSet::Set1, &Us104Key),
+            data: Port::new(DATA_PORT),
+            status: Port::new(STATUS_PORT),
+            pending_leds: None,
+        }
+    }
+
+    fn send(&mut self, byte: u8) {
+        // Give up eventually rather than hang the interrupt handler on broken hardware
+        for _ in 0..10_000 {
+            if unsafe { self.status.read() } & STATUS_INPUT_FULL == 0 {
+                unsafe { self.data.write(byte) };
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    // The LED byte can only be sent once the keyboard has acknowledged the command
+    fn update_leds(&mut self) {
+        self.pending_leds = Some(self.keyboard.modifiers().leds());
+        self.send(COMMAND_SET_LEDS);
+    }
+
+    fn is_reply(&self, byte: u8) -> bool {
+        match byte {
+            REPLY_ACK | REPLY_RESEND => true,
+            // In set 1 these are ordinary release codes (0xAA is left shift)
+            REPLY_SELF_TEST_PASSED | REPLY_ECHO | REPLY_ERROR | REPLY_OVERRUN => {
+                self.keyboard.scancode_set() == ScancodeSet::Set2
+            }
+            _ => false,
+        }
+    }
+
+    // Decoded keys go to `queue`, which is KEY_QUEUE everywhere but in tests
+    fn handle_byte(&mut self, byte: u8, queue: &KeyQueue) {
+        if self.is_reply(byte) {
+            if byte == REPLY_ACK {
+                if let Some(leds) = self.pending_leds.take() {
+                    self.send(leds);
+                }
+            }
+            return;
+        }
+
+        if let Some(event) = self.keyboard.add_byte(byte) {
+            let leds = self.keyboard.modifiers().leds();
+            let key = self.keyboard.process_event(event);
+            if self.keyboard.modifiers().leds() != leds {
+                self.update_leds();
+            }
+            // Bindings depend on what was held when the key went down, not when it is read
+            if let Some(key) = key {
+                queue.push(key, self.keyboard.modifiers());
+            }
+        }
+    }
+}
+
+// Only touched with interrupts disabled, either in the handler or via without_interrupts
+static DRIVER: Mutex<Driver> = Mutex::new(Driver::new());
+static KEY_QUEUE: KeyQueue = KeyQueue::new();
+
+pub fn init() {
+    pic::register_handler(pic::KEYBOARD_IRQ, interrupt_handler);
+}
+
+fn interrupt_handler() {
+    let mut driver = DRIVER.lock();
+    // The keyboard won't send another byte until this one has been read
+    let byte = unsafe { driver.data.read() };
+    driver.handle_byte(byte, &KEY_QUEUE);
+}
+
+pub fn set_layout(layout: &'static dyn KeyboardLayout) {
+    without_interrupts(|| DRIVER.lock().keyboard.set_layout(layout));
+}
+
+pub fn layout() -> &'static dyn KeyboardLayout {
+    without_interrupts(|| DRIVER.lock().keyboard.layout())
+}
+
+/* Selects how incoming bytes are decoded. This doesn't reprogram the
+   keyboard, it is for when the controller's set 1 translation is off.
+*/
+pub fn set_scancode_set(set: ScancodeSet) {
+    without_interrupts(|| DRIVER.lock().keyboard.set_scancode_set(set));
+}
+
+pub fn modifiers() -> Modifiers {
+    without_interrupts(|| DRIVER.lock().keyboard.modifiers())
+}
+
+pub fn pop_key() -> Option<DecodedKey> {
+    KEY_QUEUE.pop().map(|(key, _)| key)
+}
+
+// The key along with the modifiers that were held when it was pressed
+pub fn pop_key_with_modifiers() -> Option<(DecodedKey, Modifiers)> {
+    KEY_QUEUE.pop()
+}
+
+pub fn has_pending_keys() -> bool {
+    !KEY_QUEUE.is_empty()
+}
+
+// Keys thrown away because nobody was reading the queue
+pub fn dropped_keys() -> usize {
+    KEY_QUEUE.dropped()
+}
+
+// Alt+F1..F6 pick a virtual console, with either Alt key since AltGr does nothing to F keys
+fn console_key(key: DecodedKey, modifiers: Modifiers) -> Option<usize> {
+    let code = match key {
+        DecodedKey::RawKey(code) if modifiers.lalt || modifiers.ralt => code,
+        _ => return None,
+    };
+    match code {
+        KeyCode::F1 => Some(0),
+        KeyCode::F2 => Some(1),
+        KeyCode::F3 => Some(2),
+        KeyCode::F4 => Some(3),
+        KeyCode::F5 => Some(4),
+        KeyCode::F6 => Some(5),
+        _ => None,
+    }
+}
+
+// The first consumer of the key queue: print typed characters to the console on screen
+pub fn echo_pending_keys() {
+    without_interrupts(|| {
+        while let Some((key, modifiers)) = KEY_QUEUE.pop() {
+            if let Some(index) = console_key(key, modifiers) {
+                vga_buffer::switch_to(index);
+                continue;
+            }
+
+            let mut writer = vga_buffer::console(vga_buffer::active_console()).lock();
+            match key {
+                DecodedKey::Unicode(character) => {
+                    let mut buffer = [0; 4];
+                    writer.write_string(character.encode_utf8(&mut buffer));
+                }
+                // Shift+PageUp/PageDown scroll through the console history
+                DecodedKey::RawKey(KeyCode::PageUp) if modifiers.is_shifted() => writer.page_up(),
+                DecodedKey::RawKey(KeyCode::PageDown) if modifiers.is_shifted() => writer.page_down(),
+                DecodedKey::RawKey(_) => {}
+            }
+        }
+    });
+}
+
+#[test_case]
+fn test_modifiers_and_lock_keys() {
+    let mut keyboard = Keyboard::new(ScancodeSet::Set1, &Us104Key);
+    let press = |code| KeyEvent::new(code, KeyState::Down);
+    let release = |code| KeyEvent::new(code, KeyState::Up);
+
+    assert_eq!(keyboard.process_event(press(KeyCode::ShiftLeft)), None);
+    assert_eq!(keyboard.process_event(press(KeyCode::A)), Some(DecodedKey::Unicode('A')));
+    assert_eq!(keyboard.process_event(release(KeyCode::A)), None);
+    keyboard.process_event(release(KeyCode::ShiftLeft));
+
+    keyboard.process_event(press(KeyCode::CapsLock));
+    keyboard.process_event(release(KeyCode::CapsLock));
+    assert!(keyboard.modifiers().caps_lock);
+    assert_eq!(keyboard.modifiers().leds(), 0b100);
+    assert_eq!(keyboard.process_event(press(KeyCode::B)), Some(DecodedKey::Unicode('B')));
+}
+
+#[test_case]
+fn test_alt_function_keys_pick_consoles() {
+    let mut keyboard = Keyboard::new(ScancodeSet::Set1, &Us104Key);
+    let press = |code| KeyEvent::new(code, KeyState::Down);
+    let release = |code| KeyEvent::new(code, KeyState::Up);
+
+    let key = keyboard.process_event(press(KeyCode::F2)).unwrap();
+    assert_eq!(console_key(key, keyboard.modifiers()), None);
+
+    for alt in [KeyCode::AltLeft, KeyCode::AltRight] {
+        keyboard.process_event(press(alt));
+        let key = keyboard.process_event(press(KeyCode::F2)).unwrap();
+        assert_eq!(console_key(key, keyboard.modifiers()), Some(1));
+        keyboard.process_event(release(alt));
+    }
+}
+
+#[test_case]
+fn test_bytes_to_keys() {
+    // A queue of its own, so real keypresses can't get mixed in
+    let queue = KeyQueue::new();
+    let mut driver = Driver::new();
+    // Set 1: a press and release of 'q'
+    driver.handle_byte(0x10, &queue);
+    driver.handle_byte(0x90, &queue);
+    assert_eq!(queue.pop().map(|(key, _)| key), Some(DecodedKey::Unicode('q')));
+    assert_eq!(queue.pop(), None);
+}
@@ -0,0 +1,303 @@
+// layouts.rs
+use super::scancodes::KeyCode;
+use super::{DecodedKey, Modifiers};
+
+// What a printable key produces with no modifier, with shift, and with AltGr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyChars {
+    pub normal: char,
+    pub shifted: char,
+    pub altgr: Option<char>,
+    // Caps Lock only affects letters
+    pub letter: bool,
+}
+
+impl KeyChars {
+    pub const fn symbol(normal: char, shifted: char) -> KeyChars {
+        KeyChars { normal, shifted, altgr: None, letter: false }
+    }
+
+    pub const fn letter(normal: char, shifted: char) -> KeyChars {
+        KeyChars { normal, shifted, altgr: None, letter: true }
+    }
+
+    pub const fn with_altgr(self, altgr: char) -> KeyChars {
+        KeyChars { altgr: Some(altgr), ..self }
+    }
+
+    fn select(&self, modifiers: &Modifiers) -> DecodedKey {
+        if modifiers.is_altgr() {
+            if let Some(altgr) = self.altgr {
+                return DecodedKey::Unicode(altgr);
+            }
+        }
+
+        let shifted = if self.letter { modifiers.is_caps() } else { modifiers.is_shifted() };
+        let character = if shifted { self.shifted } else { self.normal };
+
+        // Ctrl+A to Ctrl+Z produce the ASCII control characters 0x01-0x1A
+        if modifiers.is_ctrl() && character.is_ascii_alphabetic() {
+            return DecodedKey::Unicode((character.to_ascii_uppercase() as u8 - b'@') as char);
+        }
+        DecodedKey::Unicode(character)
+    }
+}
+
+/* Keyboard layouts
+
+    A layout only has to describe its printable keys. Keys that behave
+    the same everywhere (Enter, Tab, the numpad, ...) are handled here,
+    anything left over is passed on as a RawKey.
+*/
+pub trait KeyboardLayout: Sync {
+    fn name(&self) -> &'static str;
+
+    fn lookup(&self, code: KeyCode) -> Option<KeyChars>;
+
+    fn map_keycode(&self, code: KeyCode, modifiers: &Modifiers) -> DecodedKey {
+        if let Some(key) = map_common(code, modifiers) {
+            return key;
+        }
+        match self.lookup(code) {
+            Some(chars) => chars.select(modifiers),
+            None => DecodedKey::RawKey(code),
+        }
+    }
+}
+
+fn map_common(code: KeyCode, modifiers: &Modifiers) -> Option<DecodedKey> {
+    use KeyCode::*;
+
+    let unicode = |c| Some(DecodedKey::Unicode(c));
+    // Without Num Lock the numpad doubles as the navigation block
+    let numpad = |digit, raw| {
+        if modifiers.num_lock {
+            Some(DecodedKey::Unicode(digit))
+        } else {
+            Some(DecodedKey::RawKey(raw))
+        }
+    };
+
+    match code {
+        Escape => unicode('\x1b'),
+        Backspace => unicode('\x08'),
+        Tab => unicode('\t'),
+        Enter | NumpadEnter => unicode('\n'),
+        Spacebar => unicode(' '),
+        Delete => unicode('\x7f'),
+        NumpadSlash => unicode('/'),
+        NumpadStar => unicode('*'),
+        NumpadMinus => unicode('-'),
+        NumpadPlus => unicode('+'),
+        Numpad0 => numpad('0', Insert),
+        Numpad1 => numpad('1', End),
+        Numpad2 => numpad('2', ArrowDown),
+        Numpad3 => numpad('3', PageDown),
+        Numpad4 => numpad('4', ArrowLeft),
+        Numpad5 => numpad('5', Numpad5),
+        Numpad6 => numpad('6', ArrowRight),
+        Numpad7 => numpad('7', Home),
+        Numpad8 => numpad('8', ArrowUp),
+        Numpad9 => numpad('9', PageUp),
+        // The decimal separator differs between layouts, so only handle Delete here
+        NumpadPeriod if !modifiers.num_lock => unicode('\x7f'),
+        _ => None,
+    }
+}
+
+// The letters that sit in the same place on all the supported layouts
+fn latin_letter(code: KeyCode) -> Option<KeyChars> {
+    use KeyCode::*;
+
+    let lower = match code {
+        A => 'a', B => 'b', C => 'c', D => 'd', E => 'e', F => 'f', G => 'g',
+        H => 'h', I => 'i', J => 'j', K => 'k', L => 'l', M => 'm', N => 'n',
+        O => 'o', P => 'p', Q => 'q', R => 'r', S => 's', T => 't', U => 'u',
+        V => 'v', W => 'w', X => 'x', Y => 'y', Z => 'z',
+        _ => return None,
+    };
+    Some(KeyChars::letter(lower, lower.to_ascii_uppercase()))
+}
+
+// US 104-key (ANSI)
+pub struct Us104Key;
+
+impl KeyboardLayout for Us104Key {
+    fn name(&self) -> &'static str {
+        "us"
+    }
+
+    fn lookup(&self, code: KeyCode) -> Option<KeyChars> {
+        use KeyCode::*;
+
+        Some(match code {
+            Backquote => KeyChars::symbol('`', '~'),
+            Key1 => KeyChars::symbol('1', '!'),
+            Key2 => KeyChars::symbol('2', '@'),
+            Key3 => KeyChars::symbol('3', '#'),
+            Key4 => KeyChars::symbol('4', '$'),
+            Key5 => KeyChars::symbol('5', '%'),
+            Key6 => KeyChars::symbol('6', '^'),
+            Key7 => KeyChars::symbol('7', '&'),
+            Key8 => KeyChars::symbol('8', '*'),
+            Key9 => KeyChars::symbol('9', '('),
+            Key0 => KeyChars::symbol('0', ')'),
+            Minus => KeyChars::symbol('-', '_'),
+            Equals => KeyChars::symbol('=', '+'),
+            BracketSquareLeft => KeyChars::symbol('[', '{'),
+            BracketSquareRight => KeyChars::symbol(']', '}'),
+            Backslash | Oem102 => KeyChars::symbol('\\', '|'),
+            SemiColon => KeyChars::symbol(';', ':'),
+            Quote => KeyChars::symbol('\'', '"'),
+            Comma => KeyChars::symbol(',', '<'),
+            Fullstop => KeyChars::symbol('.', '>'),
+            Slash => KeyChars::symbol('/', '?'),
+            NumpadPeriod => KeyChars::symbol('.', '.'),
+            code => return latin_letter(code),
+        })
+    }
+}
+
+// UK 105-key (ISO), Backslash is the # ~ key next to Enter
+pub struct Uk105Key;
+
+impl KeyboardLayout for Uk105Key {
+    fn name(&self) -> &'static str {
+        "uk"
+    }
+
+    fn lookup(&self, code: KeyCode) -> Option<KeyChars> {
+        use KeyCode::*;
+
+        Some(match code {
+            Backquote => KeyChars::symbol('`', '¬').with_altgr('¦'),
+            Key1 => KeyChars::symbol('1', '!'),
+            Key2 => KeyChars::symbol('2', '"'),
+            Key3 => KeyChars::symbol('3', '£'),
+            Key4 => KeyChars::symbol('4', '$').with_altgr('€'),
+            Key5 => KeyChars::symbol('5', '%'),
+            Key6 => KeyChars::symbol('6', '^'),
+            Key7 => KeyChars::symbol('7', '&'),
+            Key8 => KeyChars::symbol('8', '*'),
+            Key9 => KeyChars::symbol('9', '('),
+            Key0 => KeyChars::symbol('0', ')'),
+            Minus => KeyChars::symbol('-', '_'),
+            Equals => KeyChars::symbol('=', '+'),
+            BracketSquareLeft => KeyChars::symbol('[', '{'),
+            BracketSquareRight => KeyChars::symbol(']', '}'),
+            Backslash => KeyChars::symbol('#', '~'),
+            Oem102 => KeyChars::symbol('\\', '|'),
+            SemiColon => KeyChars::symbol(';', ':'),
+            Quote => KeyChars::symbol('\'', '@'),
+            Comma => KeyChars::symbol(',', '<'),
+            Fullstop => KeyChars::symbol('.', '>'),
+            Slash => KeyChars::symbol('/', '?'),
+            NumpadPeriod => KeyChars::symbol('.', '.'),
+            code => return latin_letter(code),
+        })
+    }
+}
+
+// German 105-key (ISO, QWERTZ)
+pub struct De105Key;
+
+impl KeyboardLayout for De105Key {
+    fn name(&self) -> &'static str {
+        "de"
+    }
+
+    fn lookup(&self, code: KeyCode) -> Option<KeyChars> {
+        use KeyCode::*;
+
+        Some(match code {
+            Backquote => KeyChars::symbol('^', '°'),
+            Key1 => KeyChars::symbol('1', '!'),
+            Key2 => KeyChars::symbol('2', '"').with_altgr('²'),
+            Key3 => KeyChars::symbol('3', '§').with_altgr('³'),
+            Key4 => KeyChars::symbol('4', '$'),
+            Key5 => KeyChars::symbol('5', '%'),
+            Key6 => KeyChars::symbol('6', '&'),
+            Key7 => KeyChars::symbol('7', '/').with_altgr('{'),
+            Key8 => KeyChars::symbol('8', '(').with_altgr('['),
+            Key9 => KeyChars::symbol('9', ')').with_altgr(']'),
+            Key0 => KeyChars::symbol('0', '=').with_altgr('}'),
+            Minus => KeyChars::symbol('ß', '?').with_altgr('\\'),
+            Equals => KeyChars::symbol('´', '`'),
+            Q => KeyChars::letter('q', 'Q').with_altgr('@'),
+            E => KeyChars::letter('e', 'E').with_altgr('€'),
+            // QWERTZ swaps Y and Z
+            Y => KeyChars::letter('z', 'Z'),
+            Z => KeyChars::letter('y', 'Y'),
+            M => KeyChars::letter('m', 'M').with_altgr('µ'),
+            BracketSquareLeft => KeyChars::letter('ü', 'Ü'),
+            BracketSquareRight => KeyChars::symbol('+', '*').with_altgr('~'),
+            Backslash => KeyChars::symbol('#', '\''),
+            SemiColon => KeyChars::letter('ö', 'Ö'),
+            Quote => KeyChars::letter('ä', 'Ä'),
+            Oem102 => KeyChars::symbol('<', '>').with_altgr('|'),
+            Comma => KeyChars::symbol(',', ';'),
+            Fullstop => KeyChars::symbol('.', ':'),
+            Slash => KeyChars::symbol('-', '_'),
+            NumpadPeriod => KeyChars::symbol(',', ','),
+            code => return latin_letter(code),
+        })
+    }
+}
+
+#[test_case]
+fn test_us_shift_and_caps_lock() {
+    let mut modifiers = Modifiers::new();
+    assert_eq!(Us104Key.map_keycode(KeyCode::A, &modifiers), DecodedKey::Unicode('a'));
+    assert_eq!(Us104Key.map_keycode(KeyCode::Key2, &modifiers), DecodedKey::Unicode('2'));
+
+    modifiers.caps_lock = true;
+    assert_eq!(Us104Key.map_keycode(KeyCode::A, &modifiers), DecodedKey::Unicode('A'));
+    // Caps Lock leaves symbols alone
+    assert_eq!(Us104Key.map_keycode(KeyCode::Key2, &modifiers), DecodedKey::Unicode('2'));
+
+    // Shift cancels Caps Lock for letters
+    modifiers.lshift = true;
+    assert_eq!(Us104Key.map_keycode(KeyCode::A, &modifiers), DecodedKey::Unicode('a'));
+    assert_eq!(Us104Key.map_keycode(KeyCode::Key2, &modifiers), DecodedKey::Unicode('@'));
+}
+
+#[test_case]
+fn test_ctrl_letters_are_control_characters() {
+    let mut modifiers = Modifiers::new();
+    modifiers.lctrl = true;
+    assert_eq!(Us104Key.map_keycode(KeyCode::C, &modifiers), DecodedKey::Unicode('\x03'));
+}
+
+#[test_case]
+fn test_uk_layout() {
+    let mut modifiers = Modifiers::new();
+    modifiers.rshift = true;
+    assert_eq!(Uk105Key.map_keycode(KeyCode::Key3, &modifiers), DecodedKey::Unicode('£'));
+    assert_eq!(Uk105Key.map_keycode(KeyCode::Quote, &modifiers), DecodedKey::Unicode('@'));
+}
+
+#[test_case]
+fn test_de_layout() {
+    let mut modifiers = Modifiers::new();
+    assert_eq!(De105Key.map_keycode(KeyCode::Y, &modifiers), DecodedKey::Unicode('z'));
+    assert_eq!(De105Key.map_keycode(KeyCode::SemiColon, &modifiers), DecodedKey::Unicode('ö'));
+
+    modifiers.caps_lock = true;
+    assert_eq!(De105Key.map_keycode(KeyCode::Quote, &modifiers), DecodedKey::Unicode('Ä'));
+
+    modifiers.caps_lock = false;
+    modifiers.ralt = true;
+    assert_eq!(De105Key.map_keycode(KeyCode::Q, &modifiers), DecodedKey::Unicode('@'));
+}
+
+#[test_case]
+fn test_numpad_follows_num_lock() {
+    let mut modifiers = Modifiers::new();
+    assert_eq!(
+        Us104Key.map_keycode(KeyCode::Numpad8, &modifiers),
+        DecodedKey::RawKey(KeyCode::ArrowUp)
+    );
+    modifiers.num_lock = true;
+    assert_eq!(Us104Key.map_keycode(KeyCode::Numpad8, &modifiers), DecodedKey::Unicode('8'));
+    assert_eq!(De105Key.map_keycode(KeyCode::NumpadPeriod, &modifiers), DecodedKey::Unicode(','));
+}
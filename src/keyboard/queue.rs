@@ -0,0 +1,151 @@
+// queue.rs
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use super::scancodes::KeyCode;
+use super::{DecodedKey, Modifiers};
+
+/* Lock-free key queue
+
+    A single producer (the keyboard interrupt handler) pushes keys and
+    a single consumer (normal kernel code) pops them. The producer only
+    ever moves the tail and the consumer only the head, so neither side
+    can block the other and the interrupt handler never has to wait.
+
+    One slot is always left empty to tell a full queue from an empty one.
+*/
+pub const QUEUE_SIZE: usize = 128;
+
+/* Packing a key into a u32
+
+    Bits  | Value
+    0-20  | Code point, or the KeyCode index of a raw key
+    21-29 | Modifiers held when the key was pressed
+    31    | Set for raw keys
+
+*/
+const RAW_KEY_FLAG: u32 = 1 << 31;
+const KEY_MASK: u32 = (1 << 21) - 1;
+const MODIFIERS_SHIFT: u32 = 21;
+const MODIFIERS_MASK: u32 = (1 << 9) - 1;
+
+pub struct KeyQueue {
+    slots: [AtomicU32; QUEUE_SIZE],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl KeyQueue {
+    pub const fn new() -> KeyQueue {
+        KeyQueue {
+            slots: [const { AtomicU32::new(0) }; QUEUE_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // Returns false (and counts the key as dropped) if the queue is full
+    pub fn push(&self, key: DecodedKey, modifiers: Modifiers) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % QUEUE_SIZE;
+        if next == self.head.load(Ordering::Acquire) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        self.slots[tail].store(encode(key, modifiers), Ordering::Relaxed);
+        // Publish the slot before the consumer can see the new tail
+        self.tail.store(next, Ordering::Release);
+        true
+    }
+
+    pub fn pop(&self) -> Option<(DecodedKey, Modifiers)> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let key = decode(self.slots[head].load(Ordering::Relaxed));
+        // Hand the slot back to the producer
+        self.head.store((head + 1) % QUEUE_SIZE, Ordering::Release);
+        key
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for KeyQueue {
+    fn default() -> Self {
+        KeyQueue::new()
+    }
+}
+
+fn encode(key: DecodedKey, modifiers: Modifiers) -> u32 {
+    let modifiers = (modifiers.bits() as u32) << MODIFIERS_SHIFT;
+    match key {
+        DecodedKey::Unicode(character) => modifiers | character as u32,
+        DecodedKey::RawKey(code) => RAW_KEY_FLAG | modifiers | code as u32,
+    }
+}
+
+fn decode(value: u32) -> Option<(DecodedKey, Modifiers)> {
+    let modifiers = Modifiers::from_bits(((value >> MODIFIERS_SHIFT) & MODIFIERS_MASK) as u16);
+    let key = if value & RAW_KEY_FLAG != 0 {
+        KeyCode::from_index((value & KEY_MASK) as u8).map(DecodedKey::RawKey)
+    } else {
+        core::char::from_u32(value & KEY_MASK).map(DecodedKey::Unicode)
+    };
+    key.map(|key| (key, modifiers))
+}
+
+#[cfg(test)]
+fn pop_key(queue: &KeyQueue) -> Option<DecodedKey> {
+    queue.pop().map(|(key, _)| key)
+}
+
+#[test_case]
+fn test_push_pop_in_order() {
+    let queue = KeyQueue::new();
+    assert!(queue.is_empty());
+    queue.push(DecodedKey::Unicode('ä'), Modifiers::new());
+    queue.push(DecodedKey::RawKey(KeyCode::ArrowUp), Modifiers::new());
+    assert_eq!(pop_key(&queue), Some(DecodedKey::Unicode('ä')));
+    assert_eq!(pop_key(&queue), Some(DecodedKey::RawKey(KeyCode::ArrowUp)));
+    assert_eq!(queue.pop(), None);
+}
+
+#[test_case]
+fn test_modifiers_stay_with_their_key() {
+    let queue = KeyQueue::new();
+    let alt = Modifiers { lalt: true, num_lock: true, ..Modifiers::new() };
+    queue.push(DecodedKey::RawKey(KeyCode::F2), alt);
+    queue.push(DecodedKey::Unicode('\u{10FFFF}'), Modifiers { rshift: true, ..Modifiers::new() });
+    assert_eq!(queue.pop(), Some((DecodedKey::RawKey(KeyCode::F2), alt)));
+
+    let (key, modifiers) = queue.pop().unwrap();
+    assert_eq!(key, DecodedKey::Unicode('\u{10FFFF}'));
+    assert!(modifiers.is_shifted() && !modifiers.is_alt());
+}
+
+#[test_case]
+fn test_full_queue_drops_keys() {
+    let queue = KeyQueue::new();
+    for _ in 0..QUEUE_SIZE - 1 {
+        assert!(queue.push(DecodedKey::Unicode('a'), Modifiers::new()));
+    }
+    assert!(!queue.push(DecodedKey::Unicode('b'), Modifiers::new()));
+    assert_eq!(queue.dropped(), 1);
+
+    // Draining makes room again, including across the wrap-around
+    for _ in 0..QUEUE_SIZE - 1 {
+        assert_eq!(pop_key(&queue), Some(DecodedKey::Unicode('a')));
+    }
+    assert!(queue.push(DecodedKey::Unicode('c'), Modifiers::new()));
+    assert_eq!(pop_key(&queue), Some(DecodedKey::Unicode('c')));
+}
@@ -0,0 +1,483 @@
+// scancodes.rs
+
+/* Physical keys
+
+    Keys are named after what they print on a US keyboard, the layout
+    decides what they mean. Oem102 is the extra key next to left shift
+    on ISO (UK, DE) keyboards.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum KeyCode {
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    PrintScreen, ScrollLock, Pause,
+
+    Backquote, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    Minus, Equals, Backspace,
+
+    Tab, Q, W, E, R, T, Y, U, I, O, P,
+    BracketSquareLeft, BracketSquareRight, Backslash,
+
+    CapsLock, A, S, D, F, G, H, J, K, L, SemiColon, Quote, Enter,
+
+    ShiftLeft, Oem102, Z, X, C, V, B, N, M, Comma, Fullstop, Slash, ShiftRight,
+
+    ControlLeft, WindowsLeft, AltLeft, Spacebar, AltRight, WindowsRight, Menu, ControlRight,
+
+    Insert, Home, PageUp, Delete, End, PageDown,
+    ArrowUp, ArrowLeft, ArrowDown, ArrowRight,
+
+    NumLock, NumpadSlash, NumpadStar, NumpadMinus, NumpadPlus, NumpadEnter, NumpadPeriod,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+}
+
+impl KeyCode {
+    // The inverse of `code as u8`
+    pub fn from_index(index: u8) -> Option<KeyCode> {
+        if index <= KeyCode::Numpad9 as u8 {
+            // Safe because the discriminants run from 0 to the last variant, Numpad9
+            Some(unsafe { core::mem::transmute::<u8, KeyCode>(index) })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Down,
+    Up,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+}
+
+impl KeyEvent {
+    pub const fn new(code: KeyCode, state: KeyState) -> KeyEvent {
+        KeyEvent { code, state }
+    }
+}
+
+/* Scancode sets
+
+    Set 1 is what the PS/2 controller hands us when translation is on
+    (the default on PCs and in QEMU). A key release is the make code
+    with bit 7 set.
+
+    Set 2 is what the keyboard natively sends. A key release is the
+    make code prefixed with 0xF0.
+
+    In both sets 0xE0 prefixes the keys added by the 101-key layout
+    and 0xE1 starts the Pause sequence, which has no release.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+const EXTENDED: u8 = 0xE0;
+const PAUSE: u8 = 0xE1;
+const SET2_RELEASE: u8 = 0xF0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecodeState {
+    Start,
+    Extended,
+    Release,
+    ExtendedRelease,
+    // Number of bytes of the Pause sequence still to swallow
+    Pause(u8),
+}
+
+pub struct Decoder {
+    set: ScancodeSet,
+    state: DecodeState,
+}
+
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Decoder {
+        Decoder { set, state: DecodeState::Start }
+    }
+
+    pub fn set(&self) -> ScancodeSet {
+        self.set
+    }
+
+    // Switching sets mid-sequence would misread the rest of it, so start over
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        self.set = set;
+        self.state = DecodeState::Start;
+    }
+
+    // Feeds one byte from the keyboard, returning an event once a full sequence has arrived
+    pub fn advance(&mut self, byte: u8) -> Option<KeyEvent> {
+        match self.set {
+            ScancodeSet::Set1 => self.advance_set1(byte),
+            ScancodeSet::Set2 => self.advance_set2(byte),
+        }
+    }
+
+    fn advance_set1(&mut self, byte: u8) -> Option<KeyEvent> {
+        match (self.state, byte) {
+            (DecodeState::Pause(remaining), _) => self.pause_byte(remaining),
+            (DecodeState::Start, EXTENDED) => {
+                self.state = DecodeState::Extended;
+                None
+            }
+            (DecodeState::Start, PAUSE) => {
+                self.state = DecodeState::Pause(5);
+                None
+            }
+            (state, byte) => {
+                self.state = DecodeState::Start;
+                let key_state = if byte & 0x80 != 0 { KeyState::Up } else { KeyState::Down };
+                let code = if state == DecodeState::Extended {
+                    set1_extended(byte & 0x7F)
+                } else {
+                    set1(byte & 0x7F)
+                };
+                code.map(|code| KeyEvent::new(code, key_state))
+            }
+        }
+    }
+
+    fn advance_set2(&mut self, byte: u8) -> Option<KeyEvent> {
+        match (self.state, byte) {
+            (DecodeState::Pause(remaining), _) => self.pause_byte(remaining),
+            (DecodeState::Start, EXTENDED) => {
+                self.state = DecodeState::Extended;
+                None
+            }
+            (DecodeState::Start, PAUSE) => {
+                self.state = DecodeState::Pause(7);
+                None
+            }
+            (DecodeState::Start, SET2_RELEASE) => {
+                self.state = DecodeState::Release;
+                None
+            }
+            (DecodeState::Extended, SET2_RELEASE) => {
+                self.state = DecodeState::ExtendedRelease;
+                None
+            }
+            (state, byte) => {
+                self.state = DecodeState::Start;
+                let (code, key_state) = match state {
+                    DecodeState::Extended => (set2_extended(byte), KeyState::Down),
+                    DecodeState::ExtendedRelease => (set2_extended(byte), KeyState::Up),
+                    DecodeState::Release => (set2(byte), KeyState::Up),
+                    _ => (set2(byte), KeyState::Down),
+                };
+                code.map(|code| KeyEvent::new(code, key_state))
+            }
+        }
+    }
+
+    fn pause_byte(&mut self, remaining: u8) -> Option<KeyEvent> {
+        if remaining > 1 {
+            self.state = DecodeState::Pause(remaining - 1);
+            None
+        } else {
+            self.state = DecodeState::Start;
+            Some(KeyEvent::new(KeyCode::Pause, KeyState::Down))
+        }
+    }
+}
+
+fn set1(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Key1,
+        0x03 => Key2,
+        0x04 => Key3,
+        0x05 => Key4,
+        0x06 => Key5,
+        0x07 => Key6,
+        0x08 => Key7,
+        0x09 => Key8,
+        0x0A => Key9,
+        0x0B => Key0,
+        0x0C => Minus,
+        0x0D => Equals,
+        0x0E => Backspace,
+        0x0F => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1A => BracketSquareLeft,
+        0x1B => BracketSquareRight,
+        0x1C => Enter,
+        0x1D => ControlLeft,
+        0x1E => A,
+        0x1F => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => SemiColon,
+        0x28 => Quote,
+        0x29 => Backquote,
+        0x2A => ShiftLeft,
+        0x2B => Backslash,
+        0x2C => Z,
+        0x2D => X,
+        0x2E => C,
+        0x2F => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Fullstop,
+        0x35 => Slash,
+        0x36 => ShiftRight,
+        0x37 => NumpadStar,
+        0x38 => AltLeft,
+        0x39 => Spacebar,
+        0x3A => CapsLock,
+        0x3B => F1,
+        0x3C => F2,
+        0x3D => F3,
+        0x3E => F4,
+        0x3F => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumLock,
+        0x46 => ScrollLock,
+        0x47 => Numpad7,
+        0x48 => Numpad8,
+        0x49 => Numpad9,
+        0x4A => NumpadMinus,
+        0x4B => Numpad4,
+        0x4C => Numpad5,
+        0x4D => Numpad6,
+        0x4E => NumpadPlus,
+        0x4F => Numpad1,
+        0x50 => Numpad2,
+        0x51 => Numpad3,
+        0x52 => Numpad0,
+        0x53 => NumpadPeriod,
+        0x56 => Oem102,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None,
+    })
+}
+
+// The fake shifts (0x2A, 0x36) sent around PrintScreen and the navigation keys are dropped
+fn set1_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x1C => NumpadEnter,
+        0x1D => ControlRight,
+        0x35 => NumpadSlash,
+        0x37 => PrintScreen,
+        0x38 => AltRight,
+        0x47 => Home,
+        0x48 => ArrowUp,
+        0x49 => PageUp,
+        0x4B => ArrowLeft,
+        0x4D => ArrowRight,
+        0x4F => End,
+        0x50 => ArrowDown,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5B => WindowsLeft,
+        0x5C => WindowsRight,
+        0x5D => Menu,
+        _ => return None,
+    })
+}
+
+fn set2(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0A => F8,
+        0x0B => F6,
+        0x0C => F4,
+        0x0D => Tab,
+        0x0E => Backquote,
+        0x11 => AltLeft,
+        0x12 => ShiftLeft,
+        0x14 => ControlLeft,
+        0x15 => Q,
+        0x16 => Key1,
+        0x1A => Z,
+        0x1B => S,
+        0x1C => A,
+        0x1D => W,
+        0x1E => Key2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Key4,
+        0x26 => Key3,
+        0x29 => Spacebar,
+        0x2A => V,
+        0x2B => F,
+        0x2C => T,
+        0x2D => R,
+        0x2E => Key5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Key6,
+        0x3A => M,
+        0x3B => J,
+        0x3C => U,
+        0x3D => Key7,
+        0x3E => Key8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Key0,
+        0x46 => Key9,
+        0x49 => Fullstop,
+        0x4A => Slash,
+        0x4B => L,
+        0x4C => SemiColon,
+        0x4D => P,
+        0x4E => Minus,
+        0x52 => Quote,
+        0x54 => BracketSquareLeft,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => ShiftRight,
+        0x5A => Enter,
+        0x5B => BracketSquareRight,
+        0x5D => Backslash,
+        0x61 => Oem102,
+        0x66 => Backspace,
+        0x69 => Numpad1,
+        0x6B => Numpad4,
+        0x6C => Numpad7,
+        0x70 => Numpad0,
+        0x71 => NumpadPeriod,
+        0x72 => Numpad2,
+        0x73 => Numpad5,
+        0x74 => Numpad6,
+        0x75 => Numpad8,
+        0x76 => Escape,
+        0x77 => NumLock,
+        0x78 => F11,
+        0x79 => NumpadPlus,
+        0x7A => Numpad3,
+        0x7B => NumpadMinus,
+        0x7C => NumpadStar,
+        0x7D => Numpad9,
+        0x7E => ScrollLock,
+        0x83 => F7,
+        _ => return None,
+    })
+}
+
+// As with set 1, the fake shifts (0x12, 0x59) are dropped
+fn set2_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x11 => AltRight,
+        0x14 => ControlRight,
+        0x1F => WindowsLeft,
+        0x27 => WindowsRight,
+        0x2F => Menu,
+        0x4A => NumpadSlash,
+        0x5A => NumpadEnter,
+        0x69 => End,
+        0x6B => ArrowLeft,
+        0x6C => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => ArrowDown,
+        0x74 => ArrowRight,
+        0x75 => ArrowUp,
+        0x7A => PageDown,
+        0x7C => PrintScreen,
+        0x7D => PageUp,
+        _ => return None,
+    })
+}
+
+#[test_case]
+fn test_set1_make_and_break() {
+    let mut decoder = Decoder::new(ScancodeSet::Set1);
+    assert_eq!(decoder.advance(0x1E), Some(KeyEvent::new(KeyCode::A, KeyState::Down)));
+    assert_eq!(decoder.advance(0x9E), Some(KeyEvent::new(KeyCode::A, KeyState::Up)));
+}
+
+#[test_case]
+fn test_set1_extended() {
+    let mut decoder = Decoder::new(ScancodeSet::Set1);
+    assert_eq!(decoder.advance(0xE0), None);
+    assert_eq!(decoder.advance(0x48), Some(KeyEvent::new(KeyCode::ArrowUp, KeyState::Down)));
+    assert_eq!(decoder.advance(0xE0), None);
+    assert_eq!(decoder.advance(0xC8), Some(KeyEvent::new(KeyCode::ArrowUp, KeyState::Up)));
+    // Fake shift around the navigation keys
+    assert_eq!(decoder.advance(0xE0), None);
+    assert_eq!(decoder.advance(0x2A), None);
+}
+
+#[test_case]
+fn test_set2_make_and_break() {
+    let mut decoder = Decoder::new(ScancodeSet::Set2);
+    assert_eq!(decoder.advance(0x1C), Some(KeyEvent::new(KeyCode::A, KeyState::Down)));
+    assert_eq!(decoder.advance(0xF0), None);
+    assert_eq!(decoder.advance(0x1C), Some(KeyEvent::new(KeyCode::A, KeyState::Up)));
+}
+
+#[test_case]
+fn test_set2_extended_release() {
+    let mut decoder = Decoder::new(ScancodeSet::Set2);
+    assert_eq!(decoder.advance(0xE0), None);
+    assert_eq!(decoder.advance(0x11), Some(KeyEvent::new(KeyCode::AltRight, KeyState::Down)));
+    assert_eq!(decoder.advance(0xE0), None);
+    assert_eq!(decoder.advance(0xF0), None);
+    assert_eq!(decoder.advance(0x11), Some(KeyEvent::new(KeyCode::AltRight, KeyState::Up)));
+}
+
+#[test_case]
+fn test_pause_sequence() {
+    let mut decoder = Decoder::new(ScancodeSet::Set1);
+    for &byte in &[0xE1, 0x1D, 0x45, 0xE1, 0x9D] {
+        assert_eq!(decoder.advance(byte), None);
+    }
+    assert_eq!(decoder.advance(0xC5), Some(KeyEvent::new(KeyCode::Pause, KeyState::Down)));
+
+    decoder.set_scancode_set(ScancodeSet::Set2);
+    for &byte in &[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0] {
+        assert_eq!(decoder.advance(byte), None);
+    }
+    assert_eq!(decoder.advance(0x77), Some(KeyEvent::new(KeyCode::Pause, KeyState::Down)));
+}
@@ -0,0 +1,75 @@
+// lib.rs
+
+/* The kernel library
+
+    All of the kernel subsystems live here so they can be shared by the
+    kernel binary in main.rs and the integration tests under tests/.
+    Each integration test is its own kernel that boots in isolation.
+*/
+#![no_std]
+// When testing the library itself it needs its own entry point
+#![cfg_attr(test, no_main)]
+#![feature(custom_test_frameworks)]
+// Enables the x86-interrupt calling convention used by the interrupt handlers
+#![feature(abi_x86_interrupt)]
+// Lets the kernel report failed heap allocations itself
+#![feature(alloc_error_handler)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+// Modules
+pub mod vga_buffer;
+pub mod interrupts;
+pub mod gdt;
+pub mod serial;
+pub mod qemu;
+pub mod pic;
+pub mod keyboard;
+pub mod pit;
+pub mod power;
+pub mod memory;
+pub mod allocator;
+pub mod testing;
+
+pub use testing::{test_panic_handler, test_runner, TestDescriptor, Testable};
+
+// Sets up the descriptor tables and interrupt controller every kernel binary needs
+pub fn init() {
+    gdt::init();
+    interrupts::init_idt();
+    pic::init();
+    pit::init();
+    vga_buffer::set_bell_handler(Some(pit::bell));
+    keyboard::init();
+    // Every IRQ line starts masked, so nothing fires until a driver registers
+    x86_64::instructions::interrupts::enable();
+}
+
+// Lets a panic or fatal exception print even if it interrupted another print
+pub fn unlock_consoles() {
+    vga_buffer::unlock_for_panic();
+    serial::unlock_for_panic();
+}
+
+#[cfg(test)]
+use bootloader::{entry_point, BootInfo};
+
+// Entry point for `cargo test --lib`
+#[cfg(test)]
+entry_point!(test_kernel_main);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    init();
+    memory::init(boot_info);
+    test_main();
+    power::hlt_loop()
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    test_panic_handler(info)
+}
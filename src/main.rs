@@ -5,24 +5,40 @@
 // Ovewriting the main application entry point
 #![no_main]
 #![feature(custom_test_frameworks)]
-#![test_runner(crate::test_runner)]
+#![test_runner(monkos::test_runner)]
 /* the test harness works by redefining the main function
 but there isnt one so we need to point it to a new a function name
 which can be called in _start()
 */
 #![reexport_test_harness_main = "test_main"]
 
-// Modules
-mod vga_buffer;
+use bootloader::{entry_point, BootInfo};
+
+/* The bootloader calls _start with a pointer to the boot information,
+entry_point! generates that _start for us and checks kernel_main
+has the right signature.
+*/
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    monkos::init();
+    monkos::memory::init(boot_info);
+    monkos::println!(
+        "{} KiB of {} KiB physical memory free",
+        monkos::memory::free_memory() / 1024,
+        monkos::memory::total_memory() / 1024
+    );
+    monkos::println!("{}", monkos::allocator::stats());
 
-// Disable name mangling so the compiler doesnt generate a unique name
-#[no_mangle]
-// This is the overidden entry point
-pub extern "C" fn _start() -> ! {
     // Call the test harness
     #[cfg(test)]
     test_main();
-    loop {}
+
+    loop {
+        monkos::keyboard::echo_pending_keys();
+        // Sleep until the next interrupt, which may have queued more keys
+        monkos::power::idle(monkos::keyboard::has_pending_keys);
+    }
 
 }
 
@@ -33,25 +49,31 @@ The Panic handler is called when Unreoverable Errors occur,
 The function should never return so it is marked as a divergent
 function by returning the "never" type !.
 */
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
-    println!("{}", _info);
+    // Nothing else should get to run, and the panic may have interrupted a print
+    x86_64::instructions::interrupts::disable();
+    monkos::unlock_consoles();
+    // The panic message goes to the kernel log, so make sure that is what's on screen
+    monkos::vga_buffer::switch_to(monkos::vga_buffer::LOG_CONSOLE);
 
-    loop {}
+    let uptime = monkos::pit::uptime();
+    monkos::println_colored!(monkos::vga_buffer::Color::LightRed, "[{}] {}", uptime, _info);
+    // Mirror to COM1 so headless runs still see why the kernel stopped
+    monkos::serial_println!("[{}] {}", uptime, _info);
+
+    monkos::power::after_panic()
 }
 
 #[cfg(test)]
-fn test_runner(tests: &[&dyn Fn()]) {
-    println!("Running {} tests", tests.len());
-    for test in tests {
-        test();
-    }
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    monkos::test_panic_handler(info)
 }
 
 // Simple test cases
 #[test_case]
 fn trivial_assertion() {
-    print!("Trivial assertion...");
     assert_eq!(1, 1);
-    println!("[ok]");
-}
\ No newline at end of file
+}
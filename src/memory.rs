@@ -0,0 +1,46 @@
+// memory.rs
+use bootloader::BootInfo;
+use spin::Mutex;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::VirtAddr;
+
+pub mod frame_allocator;
+pub mod paging;
+
+use frame_allocator::{BitmapFrameAllocator, FRAME_SIZE};
+
+/* Physical memory
+
+    The frame bitmap lives in the kernel's .bss so it exists before
+    there is any allocator. 4 GiB of physical memory needs 128 KiB of
+    bitmap, anything above that is left unused.
+*/
+pub const MAX_PHYSICAL_MEMORY: u64 = 4 * 1024 * 1024 * 1024;
+const BITMAP_WORDS: usize = (MAX_PHYSICAL_MEMORY / FRAME_SIZE / 64) as usize;
+
+static mut FRAME_BITMAP: [u64; BITMAP_WORDS] = [0; BITMAP_WORDS];
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator<'static>>> = Mutex::new(None);
+
+pub fn init(boot_info: &'static BootInfo) {
+    assert!(!INITIALIZED.swap(true, Ordering::SeqCst), "memory initialised twice");
+
+    // Safe because the flag above makes sure this is the only reference ever created
+    let bitmap = unsafe { &mut *addr_of_mut!(FRAME_BITMAP) };
+    *FRAME_ALLOCATOR.lock() = Some(BitmapFrameAllocator::new(&boot_info.memory_map, bitmap));
+    // Safe for the same reason, and the bootloader maps physical memory at this offset
+    unsafe { paging::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+
+    crate::allocator::init_heap().expect("heap initialisation failed");
+}
+
+// Usable physical memory in bytes, or 0 before init
+pub fn total_memory() -> u64 {
+    FRAME_ALLOCATOR.lock().as_ref().map_or(0, |allocator| allocator.total_memory())
+}
+
+pub fn free_memory() -> u64 {
+    FRAME_ALLOCATOR.lock().as_ref().map_or(0, |allocator| allocator.free_memory())
+}
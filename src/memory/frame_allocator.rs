@@ -0,0 +1,256 @@
+// frame_allocator.rs
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+pub const FRAME_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    // The frame was already free
+    DoubleFree(PhysFrame),
+    // The frame isn't in a usable region, so it can never have been allocated
+    NotUsable(PhysFrame),
+}
+
+/* Bitmap frame allocator
+
+    Every physical frame covered by the bitmap gets one bit, which is
+    set while the frame is allocated or isn't usable memory at all.
+    Frames past the end of the bitmap are simply never handed out.
+
+    The memory map is kept around so deallocation can tell a double
+    free apart from a frame that was never ours to begin with.
+*/
+pub struct BitmapFrameAllocator<'a> {
+    memory_map: &'a MemoryMap,
+    bitmap: &'a mut [u64],
+    usable_frames: usize,
+    free_frames: usize,
+    // Where the next search starts, so we don't rescan the full start of memory every time
+    next: usize,
+}
+
+impl<'a> BitmapFrameAllocator<'a> {
+    pub fn new(memory_map: &'a MemoryMap, bitmap: &'a mut [u64]) -> Self {
+        for word in bitmap.iter_mut() {
+            *word = !0;
+        }
+
+        let mut allocator = BitmapFrameAllocator {
+            memory_map,
+            bitmap,
+            usable_frames: 0,
+            free_frames: 0,
+            next: 0,
+        };
+
+        let capacity = allocator.capacity();
+        for region in memory_map.iter() {
+            if region.region_type != MemoryRegionType::Usable {
+                continue;
+            }
+            let start = region.range.start_frame_number as usize;
+            let end = (region.range.end_frame_number as usize).min(capacity);
+            for frame in start..end {
+                // Guards against overlapping regions being counted twice
+                if allocator.is_set(frame) {
+                    allocator.clear(frame);
+                    allocator.usable_frames += 1;
+                }
+            }
+        }
+        allocator.free_frames = allocator.usable_frames;
+        allocator
+    }
+
+    // Number of frames the bitmap can describe
+    pub fn capacity(&self) -> usize {
+        self.bitmap.len() * 64
+    }
+
+    pub fn total_memory(&self) -> u64 {
+        self.usable_frames as u64 * FRAME_SIZE
+    }
+
+    pub fn free_memory(&self) -> u64 {
+        self.free_frames as u64 * FRAME_SIZE
+    }
+
+    fn is_set(&self, frame: usize) -> bool {
+        self.bitmap[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set(&mut self, frame: usize) {
+        self.bitmap[frame / 64] |= 1 << (frame % 64);
+    }
+
+    fn clear(&mut self, frame: usize) {
+        self.bitmap[frame / 64] &= !(1 << (frame % 64));
+    }
+
+    fn is_usable(&self, frame: usize) -> bool {
+        frame < self.capacity()
+            && self.memory_map.iter().any(|region| {
+                region.region_type == MemoryRegionType::Usable
+                    && region.range.start_frame_number as usize <= frame
+                    && frame < region.range.end_frame_number as usize
+            })
+    }
+
+    // Finds the first clear bit at or after `start`, skipping full words at a time
+    fn find_free(&self, start: usize) -> Option<usize> {
+        let first_word = start / 64;
+        for (index, word) in self.bitmap.iter().enumerate().skip(first_word) {
+            if *word == !0 {
+                continue;
+            }
+            let mut free_bits = !*word;
+            // Ignore the bits before `start` in the first word
+            if index == first_word {
+                free_bits &= !0 << (start % 64);
+            }
+            if free_bits != 0 {
+                return Some(index * 64 + free_bits.trailing_zeros() as usize);
+            }
+        }
+        None
+    }
+
+    pub fn allocate(&mut self) -> Option<PhysFrame> {
+        if self.free_frames == 0 {
+            return None;
+        }
+
+        let frame = self.find_free(self.next).or_else(|| self.find_free(0))?;
+        self.set(frame);
+        self.free_frames -= 1;
+        self.next = frame + 1;
+        Some(PhysFrame::containing_address(PhysAddr::new(frame as u64 * FRAME_SIZE)))
+    }
+
+    pub fn deallocate(&mut self, frame: PhysFrame) -> Result<(), FrameError> {
+        let number = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        if !self.is_usable(number) {
+            return Err(FrameError::NotUsable(frame));
+        }
+        if !self.is_set(number) {
+            return Err(FrameError::DoubleFree(frame));
+        }
+
+        self.clear(number);
+        self.free_frames += 1;
+        if number < self.next {
+            self.next = number;
+        }
+        Ok(())
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate()
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator<'_> {
+    // Freeing a frame twice is a kernel bug, so treat it as fatal here
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate(frame).expect("invalid frame deallocation");
+    }
+}
+
+#[cfg(test)]
+fn test_memory_map() -> MemoryMap {
+    use bootloader::bootinfo::{FrameRange, MemoryRegion};
+
+    /* Frames | Type
+       0      | FrameZero
+       1-3    | Usable
+       4-5    | Reserved
+       6-69   | Usable (crosses a bitmap word)
+    */
+    let mut memory_map = MemoryMap::new();
+    let mut add = |start: u64, end: u64, region_type| {
+        memory_map.add_region(MemoryRegion {
+            range: FrameRange::new(start * FRAME_SIZE, end * FRAME_SIZE),
+            region_type,
+        });
+    };
+    add(0, 1, MemoryRegionType::FrameZero);
+    add(1, 4, MemoryRegionType::Usable);
+    add(4, 6, MemoryRegionType::Reserved);
+    add(6, 70, MemoryRegionType::Usable);
+    memory_map
+}
+
+#[cfg(test)]
+fn frame(number: u64) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(number * FRAME_SIZE))
+}
+
+#[test_case]
+fn test_allocates_only_usable_frames() {
+    let memory_map = test_memory_map();
+    let mut bitmap = [0; 4];
+    let mut allocator = BitmapFrameAllocator::new(&memory_map, &mut bitmap);
+    assert_eq!(allocator.total_memory(), 67 * FRAME_SIZE);
+
+    // Region boundaries: the first and last usable frames are handed out, nothing else
+    assert_eq!(allocator.allocate(), Some(frame(1)));
+    assert_eq!(allocator.allocate(), Some(frame(2)));
+    assert_eq!(allocator.allocate(), Some(frame(3)));
+    assert_eq!(allocator.allocate(), Some(frame(6)));
+    for number in 7..70 {
+        assert_eq!(allocator.allocate(), Some(frame(number)));
+    }
+    assert_eq!(allocator.allocate(), None);
+    assert_eq!(allocator.free_memory(), 0);
+}
+
+#[test_case]
+fn test_reuse_after_free() {
+    let memory_map = test_memory_map();
+    let mut bitmap = [0; 4];
+    let mut allocator = BitmapFrameAllocator::new(&memory_map, &mut bitmap);
+
+    let first = allocator.allocate().unwrap();
+    let second = allocator.allocate().unwrap();
+    assert_eq!(allocator.deallocate(first), Ok(()));
+    assert_eq!(allocator.free_memory(), 66 * FRAME_SIZE);
+    // The lowest free frame is preferred again
+    assert_eq!(allocator.allocate(), Some(first));
+    assert_ne!(allocator.allocate(), Some(second));
+}
+
+#[test_case]
+fn test_double_free_is_detected() {
+    let memory_map = test_memory_map();
+    let mut bitmap = [0; 4];
+    let mut allocator = BitmapFrameAllocator::new(&memory_map, &mut bitmap);
+
+    let allocated = allocator.allocate().unwrap();
+    assert_eq!(allocator.deallocate(allocated), Ok(()));
+    assert_eq!(allocator.deallocate(allocated), Err(FrameError::DoubleFree(allocated)));
+    assert_eq!(allocator.free_memory(), allocator.total_memory());
+}
+
+#[test_case]
+fn test_unusable_frames_cannot_be_freed() {
+    let memory_map = test_memory_map();
+    let mut bitmap = [0; 4];
+    let mut allocator = BitmapFrameAllocator::new(&memory_map, &mut bitmap);
+
+    assert_eq!(allocator.deallocate(frame(0)), Err(FrameError::NotUsable(frame(0))));
+    assert_eq!(allocator.deallocate(frame(5)), Err(FrameError::NotUsable(frame(5))));
+    assert_eq!(allocator.deallocate(frame(70)), Err(FrameError::NotUsable(frame(70))));
+}
+
+#[test_case]
+fn test_memory_past_bitmap_is_ignored() {
+    let memory_map = test_memory_map();
+    // One word only covers frames 0-63
+    let mut bitmap = [0; 1];
+    let allocator = BitmapFrameAllocator::new(&memory_map, &mut bitmap);
+    assert_eq!(allocator.total_memory(), (3 + 58) * FRAME_SIZE);
+}
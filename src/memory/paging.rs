@@ -0,0 +1,191 @@
+// paging.rs
+use spin::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, Translate, UnmapError};
+use x86_64::structures::paging::{
+    Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+use super::FRAME_ALLOCATOR;
+
+/* Virtual memory
+
+    The bootloader maps all of physical memory starting at
+    `physical_memory_offset`, so any page table frame can be reached by
+    adding the offset to its physical address. OffsetPageTable walks the
+    active tables that way.
+
+    Every function here takes the page table lock and then the frame
+    allocator lock, always in that order. Both are held with interrupts
+    disabled so a handler can't deadlock on them.
+*/
+static PAGE_TABLE: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// # Safety
+/// All of physical memory has to be mapped at `physical_memory_offset`
+/// and this may only be called once, otherwise there would be two
+/// mutable references to the level 4 table.
+pub unsafe fn init(physical_memory_offset: VirtAddr) {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::SeqCst);
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    let table = OffsetPageTable::new(level_4_table, physical_memory_offset);
+    interrupts::without_interrupts(|| *PAGE_TABLE.lock() = Some(table));
+}
+
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_frame, _) = Cr3::read();
+    let virt = physical_memory_offset + level_4_frame.start_address().as_u64();
+    &mut *virt.as_mut_ptr()
+}
+
+// Where a physical address can be read through the bootloader's mapping of all memory
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst) + addr.as_u64())
+}
+
+fn with_page_table<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut OffsetPageTable<'static>) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut table = PAGE_TABLE.lock();
+        f(table.as_mut().expect("paging not initialised"))
+    })
+}
+
+// Follows the page tables, including huge pages, returning None if the address isn't mapped
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    with_page_table(|table| table.translate_addr(addr))
+}
+
+/* Maps a page of any size to a frame of the same size
+
+   Missing intermediate tables are taken from the frame allocator. The
+   TLB entry is flushed so the new mapping is used straight away.
+*/
+/// # Safety
+/// The caller has to make sure the frame isn't already in use in a way
+/// that aliasing it would break memory safety.
+pub unsafe fn map<S: PageSize>(
+    page: Page<S>,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<S>>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    with_page_table(|table| {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let allocator = allocator.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+        // Intermediate tables need to allow anything a leaf below them allows
+        let parent_flags = flags
+            & (PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+        table
+            .map_to_with_table_flags(page, frame, flags, parent_flags, allocator)
+            .map(|flush| flush.flush())
+    })
+}
+
+/* Maps a 4 KiB page to a newly allocated frame
+
+   Safe because the frame is fresh, nothing else can be using it.
+*/
+pub fn map_new(page: Page, flags: PageTableFlags) -> Result<PhysFrame, MapToError<Size4KiB>> {
+    let frame = interrupts::without_interrupts(|| {
+        FRAME_ALLOCATOR.lock().as_mut().and_then(|allocator| allocator.allocate())
+    })
+    .ok_or(MapToError::FrameAllocationFailed)?;
+
+    unsafe { map(page, frame, flags) }.map(|_| frame).inspect_err(|_| {
+        // Don't leak the frame if the mapping itself failed
+        interrupts::without_interrupts(|| {
+            if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+                let _ = allocator.deallocate(frame);
+            }
+        });
+    })
+}
+
+// Removes a mapping and returns the frame it pointed at, which the caller may free
+pub fn unmap<S: PageSize>(page: Page<S>) -> Result<PhysFrame<S>, UnmapError>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    with_page_table(|table| {
+        let (frame, flush) = table.unmap(page)?;
+        flush.flush();
+        Ok(frame)
+    })
+}
+
+// Replaces the flags of an existing mapping
+/// # Safety
+/// Removing PRESENT or WRITABLE from a page that still has live
+/// references into it will fault the next time they're used.
+pub unsafe fn update_flags<S: PageSize>(
+    page: Page<S>,
+    flags: PageTableFlags,
+) -> Result<(), FlagUpdateError>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    with_page_table(|table| table.update_flags(page, flags).map(|flush| flush.flush()))
+}
+
+#[cfg(test)]
+use x86_64::structures::paging::Size2MiB;
+
+// Far away from anything the bootloader maps
+#[cfg(test)]
+const TEST_PAGE: u64 = 0x_4444_0000_0000;
+
+#[test_case]
+fn test_translate_kernel_address() {
+    // The VGA buffer is identity mapped by the bootloader
+    assert_eq!(translate(VirtAddr::new(0xb8000)), Some(PhysAddr::new(0xb8000)));
+    assert_eq!(translate(VirtAddr::new(TEST_PAGE)), None);
+}
+
+#[test_case]
+fn test_map_write_unmap() {
+    let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(TEST_PAGE));
+    let frame = map_new(page, PageTableFlags::PRESENT | PageTableFlags::WRITABLE).unwrap();
+    assert_eq!(translate(page.start_address() + 8u64), Some(frame.start_address() + 8u64));
+
+    // A write through the new page shows up in the frame
+    let value = 0x_f00d_cafe_u64;
+    unsafe {
+        page.start_address().as_mut_ptr::<u64>().write_volatile(value);
+        assert_eq!(phys_to_virt(frame.start_address()).as_ptr::<u64>().read_volatile(), value);
+    }
+
+    unsafe { update_flags(page, PageTableFlags::PRESENT).unwrap() };
+    assert_eq!(unmap(page).unwrap(), frame);
+    assert_eq!(translate(page.start_address()), None);
+    assert!(matches!(unmap(page), Err(UnmapError::PageNotMapped)));
+
+    interrupts::without_interrupts(|| {
+        FRAME_ALLOCATOR.lock().as_mut().unwrap().deallocate(frame).unwrap();
+    });
+}
+
+#[test_case]
+fn test_map_huge_page() {
+    // The first 2 MiB of physical memory hold the VGA buffer, so map them read-only
+    let page: Page<Size2MiB> = Page::containing_address(VirtAddr::new(TEST_PAGE + 0x20_0000));
+    let frame: PhysFrame<Size2MiB> = PhysFrame::containing_address(PhysAddr::new(0));
+    unsafe { map(page, frame, PageTableFlags::PRESENT).unwrap() };
+
+    let vga = page.start_address() + 0xb8000u64;
+    assert_eq!(translate(vga), Some(PhysAddr::new(0xb8000)));
+    assert_eq!(
+        unsafe { vga.as_ptr::<u16>().read_volatile() },
+        unsafe { (0xb8000 as *const u16).read_volatile() }
+    );
+
+    assert_eq!(unmap(page).unwrap(), frame);
+    assert_eq!(translate(vga), None);
+}
@@ -0,0 +1,308 @@
+// pic.rs
+use x86_64::instructions::port::Port;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::{HandlerFunc, InterruptStackFrame};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::interrupts;
+
+/* 8259 Programmable Interrupt Controller
+
+    The PC has two PICs chained together, each with 8 interrupt lines.
+    The secondary PIC is wired to line 2 of the primary, giving IRQ0-15.
+
+    By default the primary PIC delivers IRQ0-7 on vectors 8-15, which
+    clash with the CPU exceptions, so both controllers get remapped to
+    the first free vectors: IRQ0-7 -> 32-39 and IRQ8-15 -> 40-47.
+*/
+pub const PIC_1_OFFSET: u8 = interrupts::FIRST_FREE_VECTOR;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+pub const IRQ_COUNT: u8 = 16;
+
+// Well known IRQ lines
+pub const TIMER_IRQ: u8 = 0;
+pub const KEYBOARD_IRQ: u8 = 1;
+const CASCADE_IRQ: u8 = 2;
+
+// Initialisation Command Words
+const ICW1_INIT: u8 = 0x10;
+const ICW1_ICW4: u8 = 0x01;
+const ICW4_8086: u8 = 0x01;
+// Operation Command Words
+const OCW2_END_OF_INTERRUPT: u8 = 0x20;
+const OCW3_READ_ISR: u8 = 0x0B;
+
+struct Pic {
+    offset: u8,
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    fn handles_interrupt(&self, vector: u8) -> bool {
+        self.offset <= vector && vector < self.offset + 8
+    }
+
+    unsafe fn end_of_interrupt(&mut self) {
+        self.command.write(OCW2_END_OF_INTERRUPT);
+    }
+
+    // The In-Service Register has a bit set for every IRQ currently being handled
+    unsafe fn in_service(&mut self) -> u8 {
+        self.command.write(OCW3_READ_ISR);
+        self.command.read()
+    }
+}
+
+struct ChainedPics {
+    pics: [Pic; 2],
+}
+
+impl ChainedPics {
+    const fn new(offset1: u8, offset2: u8) -> ChainedPics {
+        ChainedPics {
+            pics: [
+                Pic { offset: offset1, command: Port::new(0x20), data: Port::new(0x21) },
+                Pic { offset: offset2, command: Port::new(0xA0), data: Port::new(0xA1) },
+            ],
+        }
+    }
+
+    /* Runs the initialisation sequence on both controllers
+
+        ICW1 starts initialisation, ICW2 sets the vector offset,
+        ICW3 describes the cascade wiring and ICW4 selects 8086 mode.
+        The PICs are slow so we give them time between each write.
+    */
+    unsafe fn initialize(&mut self) {
+        // Writing to the unused port 0x80 takes long enough to act as a delay
+        let mut wait_port: Port<u8> = Port::new(0x80);
+        let mut wait = || wait_port.write(0);
+
+        self.pics[0].command.write(ICW1_INIT | ICW1_ICW4);
+        wait();
+        self.pics[1].command.write(ICW1_INIT | ICW1_ICW4);
+        wait();
+
+        self.pics[0].data.write(self.pics[0].offset);
+        wait();
+        self.pics[1].data.write(self.pics[1].offset);
+        wait();
+
+        // Tell the primary there is a secondary on line 2, and the secondary its identity
+        self.pics[0].data.write(1 << CASCADE_IRQ);
+        wait();
+        self.pics[1].data.write(CASCADE_IRQ);
+        wait();
+
+        self.pics[0].data.write(ICW4_8086);
+        wait();
+        self.pics[1].data.write(ICW4_8086);
+        wait();
+
+        // Start with every line masked except the cascade
+        self.set_masks(!(1 << CASCADE_IRQ));
+    }
+
+    fn handles_interrupt(&self, vector: u8) -> bool {
+        self.pics.iter().any(|pic| pic.handles_interrupt(vector))
+    }
+
+    // Interrupts routed through the secondary PIC have to be acknowledged on both
+    unsafe fn notify_end_of_interrupt(&mut self, vector: u8) {
+        if self.handles_interrupt(vector) {
+            if self.pics[1].handles_interrupt(vector) {
+                self.pics[1].end_of_interrupt();
+            }
+            self.pics[0].end_of_interrupt();
+        }
+    }
+
+    unsafe fn in_service(&mut self) -> u16 {
+        (self.pics[1].in_service() as u16) << 8 | self.pics[0].in_service() as u16
+    }
+
+    // A set bit masks (disables) the corresponding IRQ line
+    unsafe fn masks(&mut self) -> u16 {
+        (self.pics[1].data.read() as u16) << 8 | self.pics[0].data.read() as u16
+    }
+
+    unsafe fn set_masks(&mut self, masks: u16) {
+        self.pics[0].data.write(masks as u8);
+        self.pics[1].data.write((masks >> 8) as u8);
+    }
+}
+
+static PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET));
+
+/* Device driver handlers
+
+    Drivers attach a handler to an IRQ line. The PIC code takes care of
+    spurious interrupts and the end of interrupt, so handlers only
+    need to service their device.
+
+    The locks below are also taken from interrupt context, so every
+    access from normal code happens with interrupts disabled.
+*/
+pub type IrqHandler = fn();
+
+static HANDLERS: Mutex<[Option<IrqHandler>; IRQ_COUNT as usize]> =
+    Mutex::new([None; IRQ_COUNT as usize]);
+static SPURIOUS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init() {
+    without_interrupts(|| unsafe { PICS.lock().initialize() });
+
+    for (irq, stub) in IRQ_STUBS.iter().enumerate() {
+        interrupts::set_handler(PIC_1_OFFSET + irq as u8, *stub);
+    }
+}
+
+// Attaches a handler to an IRQ line and unmasks it
+pub fn register_handler(irq: u8, handler: IrqHandler) {
+    assert!(irq < IRQ_COUNT, "invalid IRQ {}", irq);
+    without_interrupts(|| {
+        HANDLERS.lock()[irq as usize] = Some(handler);
+    });
+    unmask(irq);
+}
+
+// Masks an IRQ line and detaches its handler
+pub fn unregister_handler(irq: u8) {
+    assert!(irq < IRQ_COUNT, "invalid IRQ {}", irq);
+    mask(irq);
+    without_interrupts(|| {
+        HANDLERS.lock()[irq as usize] = None;
+    });
+}
+
+pub fn mask(irq: u8) {
+    assert!(irq < IRQ_COUNT, "invalid IRQ {}", irq);
+    without_interrupts(|| unsafe {
+        let mut pics = PICS.lock();
+        let masks = pics.masks();
+        pics.set_masks(masks | 1 << irq);
+    });
+}
+
+pub fn unmask(irq: u8) {
+    assert!(irq < IRQ_COUNT, "invalid IRQ {}", irq);
+    without_interrupts(|| unsafe {
+        let mut pics = PICS.lock();
+        let mut masks = pics.masks() & !(1 << irq);
+        // Lines on the secondary PIC only arrive if the cascade is open too
+        if irq >= 8 {
+            masks &= !(1 << CASCADE_IRQ);
+        }
+        pics.set_masks(masks);
+    });
+}
+
+pub fn is_masked(irq: u8) -> bool {
+    assert!(irq < IRQ_COUNT, "invalid IRQ {}", irq);
+    without_interrupts(|| unsafe { PICS.lock().masks() } & 1 << irq != 0)
+}
+
+// Number of spurious IRQ7/IRQ15 interrupts seen since boot
+pub fn spurious_count() -> usize {
+    SPURIOUS_COUNT.load(Ordering::Relaxed)
+}
+
+/* Spurious interrupts
+
+    If an IRQ goes away before the CPU acknowledges it, the PIC delivers
+    its lowest priority line (IRQ7, or IRQ15 on the secondary) instead.
+    A real interrupt has its bit set in the In-Service Register, a
+    spurious one doesn't and must not be acknowledged on that PIC.
+    A spurious IRQ15 still went through the cascade line of the primary,
+    so the primary needs its end of interrupt.
+*/
+fn is_spurious(irq: u8) -> bool {
+    if irq != 7 && irq != 15 {
+        return false;
+    }
+
+    let mut pics = PICS.lock();
+    if unsafe { pics.in_service() } & 1 << irq != 0 {
+        return false;
+    }
+
+    if irq == 15 {
+        unsafe { pics.pics[0].end_of_interrupt() };
+    }
+    SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+fn dispatch(irq: u8) {
+    if is_spurious(irq) {
+        return;
+    }
+
+    // Copy the handler out so the lock isn't held while it runs
+    let handler = HANDLERS.lock()[irq as usize];
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq) };
+}
+
+// Each IRQ needs its own IDT entry so the dispatcher knows which line fired
+macro_rules! irq_stubs {
+    ($($irq:literal => $stub:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $stub(_stack_frame: InterruptStackFrame) {
+                dispatch($irq);
+            }
+        )*
+
+        const IRQ_STUBS: [HandlerFunc; IRQ_COUNT as usize] = [$($stub),*];
+    };
+}
+
+irq_stubs! {
+    0 => irq0_handler,
+    1 => irq1_handler,
+    2 => irq2_handler,
+    3 => irq3_handler,
+    4 => irq4_handler,
+    5 => irq5_handler,
+    6 => irq6_handler,
+    7 => irq7_handler,
+    8 => irq8_handler,
+    9 => irq9_handler,
+    10 => irq10_handler,
+    11 => irq11_handler,
+    12 => irq12_handler,
+    13 => irq13_handler,
+    14 => irq14_handler,
+    15 => irq15_handler,
+}
+
+#[test_case]
+fn test_mask_and_unmask() {
+    // IRQ5 is normally unused so toggling it is harmless
+    mask(5);
+    assert!(is_masked(5));
+    unmask(5);
+    assert!(!is_masked(5));
+    mask(5);
+}
+
+#[test_case]
+fn test_unmask_secondary_opens_cascade() {
+    mask(CASCADE_IRQ);
+    unmask(13);
+    assert!(!is_masked(CASCADE_IRQ));
+    mask(13);
+}
+
+#[test_case]
+fn test_handles_interrupt() {
+    let pics = ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET);
+    assert!(!pics.handles_interrupt(PIC_1_OFFSET - 1));
+    assert!(pics.handles_interrupt(PIC_1_OFFSET));
+    assert!(pics.handles_interrupt(PIC_2_OFFSET + 7));
+    assert!(!pics.handles_interrupt(PIC_2_OFFSET + 8));
+}
@@ -0,0 +1,225 @@
+// pit.rs
+use x86_64::instructions::port::Port;
+use x86_64::instructions::interrupts;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+use core::fmt;
+use crate::pic;
+
+/* 8254 Programmable Interval Timer
+
+    The PIT is driven by a 1.193182 MHz oscillator. Channel 0 divides
+    that clock down and raises IRQ0 once per period, which gives us a
+    steady tick to count time with.
+*/
+const OSCILLATOR_HZ: u32 = 1_193_182;
+pub const DEFAULT_FREQUENCY_HZ: u32 = 1000;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const CHANNEL_2_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+// Bit 0 gates channel 2, bit 1 connects it to the PC speaker
+const SPEAKER_PORT: u16 = 0x61;
+const SPEAKER_ENABLE: u8 = 0b11;
+
+/* Constructing the command byte
+
+    Bits | Value
+    0    | BCD mode (0 = binary)
+    1-3  | Operating mode (3 = square wave generator)
+    4-5  | Access mode (3 = low byte then high byte)
+    6-7  | Channel
+
+*/
+const COMMAND_CHANNEL_0_SQUARE_WAVE: u8 = 0x36;
+const COMMAND_CHANNEL_2_SQUARE_WAVE: u8 = 0xB6;
+
+pub const BELL_FREQUENCY_HZ: u32 = 750;
+pub const BELL_DURATION_MS: u64 = 100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// Nanoseconds are counted separately so uptime survives frequency changes
+static UPTIME_NANOS: AtomicU64 = AtomicU64::new(0);
+static FREQUENCY_HZ: AtomicU32 = AtomicU32::new(0);
+static TICK_NANOS: AtomicU64 = AtomicU64::new(0);
+// Tick at which the speaker goes quiet again, 0 while it is silent
+static BEEP_UNTIL: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    set_frequency(DEFAULT_FREQUENCY_HZ);
+    pic::register_handler(pic::TIMER_IRQ, tick);
+}
+
+// Returns the frequency actually programmed, which is rounded to a whole divisor
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = square_wave_divisor(hz);
+    let actual = OSCILLATOR_HZ / divisor;
+
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut channel_0: Port<u8> = Port::new(CHANNEL_0_PORT);
+    interrupts::without_interrupts(|| unsafe {
+        command.write(COMMAND_CHANNEL_0_SQUARE_WAVE);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+
+        FREQUENCY_HZ.store(actual, Ordering::SeqCst);
+        TICK_NANOS.store(divisor as u64 * 1_000_000_000 / OSCILLATOR_HZ as u64, Ordering::SeqCst);
+    });
+    actual
+}
+
+// Square wave mode can't divide by 1, and a divisor of 0 means 65536, the slowest the PIT can go
+fn square_wave_divisor(hz: u32) -> u32 {
+    (OSCILLATOR_HZ / hz.max(1)).clamp(2, 65536)
+}
+
+pub fn frequency() -> u32 {
+    FREQUENCY_HZ.load(Ordering::SeqCst)
+}
+
+fn tick() {
+    let ticks = TICKS.fetch_add(1, Ordering::SeqCst) + 1;
+    UPTIME_NANOS.fetch_add(TICK_NANOS.load(Ordering::Relaxed), Ordering::SeqCst);
+
+    let beep_until = BEEP_UNTIL.load(Ordering::SeqCst);
+    if beep_until != 0 && ticks >= beep_until {
+        speaker_off();
+    }
+}
+
+/* PC speaker
+
+    Channel 2 produces a square wave that the speaker plays while it is
+    connected. A beep only starts the tone, the timer interrupt stops it
+    later, so it can be used with interrupts disabled or a lock held.
+*/
+pub fn beep(hz: u32, ms: u64) {
+    let divisor = square_wave_divisor(hz);
+
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut channel_2: Port<u8> = Port::new(CHANNEL_2_PORT);
+    let mut speaker: Port<u8> = Port::new(SPEAKER_PORT);
+    interrupts::without_interrupts(|| unsafe {
+        command.write(COMMAND_CHANNEL_2_SQUARE_WAVE);
+        channel_2.write(divisor as u8);
+        channel_2.write((divisor >> 8) as u8);
+
+        let value = speaker.read();
+        speaker.write(value | SPEAKER_ENABLE);
+        BEEP_UNTIL.store(deadline(ms_to_ticks(ms).max(1)), Ordering::SeqCst);
+    });
+}
+
+pub fn speaker_off() {
+    let mut speaker: Port<u8> = Port::new(SPEAKER_PORT);
+    interrupts::without_interrupts(|| unsafe {
+        let value = speaker.read();
+        speaker.write(value & !SPEAKER_ENABLE);
+        BEEP_UNTIL.store(0, Ordering::SeqCst);
+    });
+}
+
+// What the console rings for BEL
+pub fn bell() {
+    beep(BELL_FREQUENCY_HZ, BELL_DURATION_MS);
+}
+
+// Timer interrupts since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::SeqCst)
+}
+
+pub fn uptime() -> Uptime {
+    Uptime(Duration::from_nanos(UPTIME_NANOS.load(Ordering::SeqCst)))
+}
+
+// Rounds up, so sleeping never returns early
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    let tick_nanos = TICK_NANOS.load(Ordering::SeqCst).max(1);
+    ms.saturating_mul(1_000_000).div_ceil(tick_nanos)
+}
+
+// The tick count `ticks_to_wait` from now, a wait too long to count just never ends
+fn deadline(ticks_to_wait: u64) -> u64 {
+    ticks().saturating_add(ticks_to_wait)
+}
+
+/* Sleeping
+
+    Instead of spinning, the CPU halts until the next interrupt and
+    checks the deadline again. Any interrupt wakes it up, so this only
+    works with interrupts enabled and the timer running.
+*/
+pub fn sleep_ticks(ticks_to_wait: u64) {
+    assert!(interrupts::are_enabled(), "sleeping with interrupts disabled would never wake up");
+
+    let deadline = deadline(ticks_to_wait);
+    while ticks() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+pub fn sleep_ms(ms: u64) {
+    sleep_ticks(ms_to_ticks(ms));
+}
+
+// Time since the timer started, printed as seconds like the timestamps in a kernel log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Uptime(pub Duration);
+
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:5}.{:06}", self.0.as_secs(), self.0.subsec_micros())
+    }
+}
+
+#[test_case]
+fn test_ticks_advance_while_sleeping() {
+    let start = ticks();
+    sleep_ticks(3);
+    assert!(ticks() >= start + 3);
+}
+
+#[test_case]
+fn test_sleep_ms_advances_uptime() {
+    let start = uptime();
+    sleep_ms(10);
+    assert!(uptime().0 - start.0 >= Duration::from_millis(10));
+}
+
+#[test_case]
+fn test_beep_stops_by_itself() {
+    beep(BELL_FREQUENCY_HZ, 5);
+    assert_ne!(BEEP_UNTIL.load(Ordering::SeqCst), 0);
+    sleep_ms(10);
+    assert_eq!(BEEP_UNTIL.load(Ordering::SeqCst), 0);
+}
+
+#[test_case]
+fn test_divisor_and_tick_limits() {
+    assert_eq!(square_wave_divisor(OSCILLATOR_HZ), 2);
+    assert_eq!(square_wave_divisor(u32::MAX), 2);
+    assert_eq!(square_wave_divisor(1), 65536);
+    assert_eq!(square_wave_divisor(DEFAULT_FREQUENCY_HZ), 1193);
+    // Huge sleeps saturate instead of wrapping around to short ones
+    assert!(ms_to_ticks(u64::MAX) >= ms_to_ticks(u64::MAX / 1_000_000));
+    assert_eq!(deadline(ms_to_ticks(u64::MAX)), u64::MAX);
+}
+
+#[test_case]
+fn test_uptime_format() {
+    use core::fmt::Write;
+
+    struct Buffer([u8; 16], usize);
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0[self.1..self.1 + s.len()].copy_from_slice(s.as_bytes());
+            self.1 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut buffer = Buffer([0; 16], 0);
+    write!(buffer, "{}", Uptime(Duration::from_micros(12_345_678))).unwrap();
+    assert_eq!(&buffer.0[..buffer.1], b"   12.345678");
+}
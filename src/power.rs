@@ -0,0 +1,185 @@
+// power.rs
+use x86_64::instructions::port::Port;
+use x86_64::instructions::{hlt, interrupts};
+use spin::Mutex;
+#[cfg(test)]
+use crate::pit;
+
+/* CPU idle
+
+    hlt stops the CPU until the next interrupt arrives, so waiting this
+    way doesn't burn host CPU time under QEMU the way `loop {}` does.
+*/
+pub fn hlt_loop() -> ! {
+    loop {
+        hlt();
+    }
+}
+
+/* Sleeps until the next interrupt unless there is already work to do.
+
+   The check happens with interrupts disabled and `sti; hlt` re-enables
+   them atomically, so work queued by an interrupt can't slip in between
+   the check and the hlt and leave us asleep with work pending.
+*/
+pub fn idle<F>(has_work: F)
+where
+    F: Fn() -> bool,
+{
+    interrupts::disable();
+    if has_work() {
+        interrupts::enable();
+    } else {
+        interrupts::enable_and_hlt();
+    }
+}
+
+/* Shutdown
+
+    Without ACPI there is no standard way to power off, but emulators
+    provide magic ports: 0x604 on QEMU, 0xB004 on Bochs and older QEMU
+    and 0x4004 on VirtualBox. If none of them work we just halt.
+*/
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    unsafe {
+        Port::<u16>::new(0x604).write(0x2000);
+        Port::<u16>::new(0xB004).write(0x2000);
+        Port::<u16>::new(0x4004).write(0x3400);
+    }
+    hlt_loop()
+}
+
+const KEYBOARD_CONTROLLER_STATUS: u16 = 0x64;
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 1 << 1;
+const KEYBOARD_CONTROLLER_PULSE_RESET: u8 = 0xFE;
+
+/* Reboot
+
+    The 8042 keyboard controller can pulse the CPU reset line. If that
+    doesn't work we force a triple fault by loading an empty IDT and
+    raising an exception, which resets the machine.
+*/
+pub fn reboot() -> ! {
+    use x86_64::structures::DescriptorTablePointer;
+    use x86_64::instructions::tables::lidt;
+    use x86_64::VirtAddr;
+
+    interrupts::disable();
+    let mut controller: Port<u8> = Port::new(KEYBOARD_CONTROLLER_STATUS);
+    unsafe {
+        for _ in 0..10_000 {
+            if controller.read() & KEYBOARD_CONTROLLER_INPUT_FULL == 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        controller.write(KEYBOARD_CONTROLLER_PULSE_RESET);
+    }
+
+    // Still here, so fall back to a triple fault
+    let empty_idt = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    unsafe { lidt(&empty_idt) };
+    interrupts::int3();
+
+    hlt_loop()
+}
+
+// What the kernel does once a panic has been reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    Halt,
+    Reboot { delay_ms: u64 },
+    Shutdown { delay_ms: u64 },
+}
+
+static PANIC_ACTION: Mutex<PanicAction> = Mutex::new(PanicAction::Halt);
+
+pub fn set_panic_action(action: PanicAction) {
+    interrupts::without_interrupts(|| *PANIC_ACTION.lock() = action);
+}
+
+pub fn panic_action() -> PanicAction {
+    interrupts::without_interrupts(|| *PANIC_ACTION.lock())
+}
+
+// Writes to the POST code port take about a microsecond and have no other effect
+const IO_DELAY_PORT: u16 = 0x80;
+const IO_DELAYS_PER_MS: u64 = 1000;
+
+/* Busy-waits roughly `ms` milliseconds
+
+    Unlike pit::sleep_ms this needs neither interrupts nor the timer, so
+    it also works when the panic came before pit::init, from inside the
+    timer handler or while the PIC lock was held. The time is only
+    approximate.
+*/
+fn io_delay_ms(ms: u64) {
+    let mut port: Port<u8> = Port::new(IO_DELAY_PORT);
+    for _ in 0..ms.saturating_mul(IO_DELAYS_PER_MS) {
+        unsafe { port.write(0) };
+    }
+}
+
+/* Called at the end of the panic handler
+
+    The delay gives a chance to read the panic message before the
+    machine goes away. Interrupts stay disabled, so nothing else runs
+    while we wait.
+*/
+pub fn after_panic() -> ! {
+    // The panic may have hit while the lock was held, in which case just halt
+    let action = PANIC_ACTION.try_lock().map(|action| *action).unwrap_or(PanicAction::Halt);
+
+    let delay = |delay_ms| {
+        interrupts::disable();
+        io_delay_ms(delay_ms);
+    };
+
+    match action {
+        PanicAction::Halt => {
+            interrupts::disable();
+            hlt_loop()
+        }
+        PanicAction::Reboot { delay_ms } => {
+            delay(delay_ms);
+            reboot()
+        }
+        PanicAction::Shutdown { delay_ms } => {
+            delay(delay_ms);
+            shutdown()
+        }
+    }
+}
+
+#[test_case]
+fn test_idle_returns_with_pending_work() {
+    idle(|| true);
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn test_idle_wakes_on_interrupt() {
+    // The timer interrupt ends the hlt
+    let start = pit::ticks();
+    idle(|| false);
+    assert!(pit::ticks() > start);
+}
+
+#[test_case]
+fn test_io_delay_without_interrupts() {
+    // Has to return even though no timer tick can arrive
+    interrupts::without_interrupts(|| {
+        let start = pit::ticks();
+        io_delay_ms(2);
+        assert_eq!(pit::ticks(), start);
+    });
+}
+
+#[test_case]
+fn test_set_panic_action() {
+    let action = PanicAction::Reboot { delay_ms: 5000 };
+    set_panic_action(action);
+    assert_eq!(panic_action(), action);
+    set_panic_action(PanicAction::Halt);
+}
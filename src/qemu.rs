@@ -0,0 +1,24 @@
+// qemu.rs
+use x86_64::instructions::port::Port;
+
+/* QEMU isa-debug-exit device
+
+    Writing a value to the device's I/O port makes QEMU exit with the
+    status (value << 1) | 1. The codes are chosen so they don't clash
+    with QEMU's own exit codes, see test-success-exit-code in Cargo.toml.
+*/
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    let mut port = Port::new(ISA_DEBUG_EXIT_PORT);
+    unsafe {
+        port.write(exit_code as u32);
+    }
+}
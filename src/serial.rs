@@ -0,0 +1,234 @@
+// serial.rs
+use x86_64::instructions::port::Port;
+use spin::Mutex;
+use lazy_static::lazy_static;
+use core::fmt;
+
+// I/O base addresses of the standard PC serial ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum ComPort {
+    Com1 = 0x3F8,
+    Com2 = 0x2F8,
+    Com3 = 0x3E8,
+    Com4 = 0x2E8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DataBits {
+    Five = 0b00,
+    Six = 0b01,
+    Seven = 0b10,
+    Eight = 0b11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum StopBits {
+    One = 0b0,
+    Two = 0b1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Parity {
+    None = 0b000,
+    Odd = 0b001,
+    Even = 0b011,
+    Mark = 0b101,
+    Space = 0b111,
+}
+
+/* Constructing the Line Control Register byte
+
+    Bits | Value
+    0-1  | Data bits
+    2    | Stop bits
+    3-5  | Parity
+    7    | Divisor Latch Access Bit (DLAB)
+
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineControl {
+    pub data_bits: DataBits,
+    pub stop_bits: StopBits,
+    pub parity: Parity,
+}
+
+impl LineControl {
+    // The usual 8N1 configuration
+    pub const fn new() -> LineControl {
+        LineControl {
+            data_bits: DataBits::Eight,
+            stop_bits: StopBits::One,
+            parity: Parity::None,
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        (self.parity as u8) << 3 | (self.stop_bits as u8) << 2 | self.data_bits as u8
+    }
+}
+
+impl Default for LineControl {
+    fn default() -> Self {
+        LineControl::new()
+    }
+}
+
+// The UART clock runs at 115200 Hz and is divided down to the baud rate
+const UART_CLOCK: u32 = 115200;
+pub const DEFAULT_BAUD: u32 = 38400;
+
+const LINE_CONTROL_DLAB: u8 = 1 << 7;
+// Enable and clear both FIFOs, interrupt once 14 bytes are queued
+const FIFO_ENABLE_CLEAR_14: u8 = 0xC7;
+// Data Terminal Ready, Request To Send and OUT2
+const MODEM_DTR_RTS_OUT2: u8 = 0x0B;
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/* 16550 UART
+
+    Each port is a bank of 8 registers starting at the base address.
+    With DLAB set, the first two registers hold the baud rate divisor
+    instead of the data and interrupt enable registers.
+*/
+pub struct SerialPort {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: Port<u8>,
+}
+
+impl SerialPort {
+    pub const fn new(port: ComPort) -> SerialPort {
+        let base = port as u16;
+        SerialPort {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: Port::new(base + 5),
+        }
+    }
+
+    pub fn init(&mut self, baud: u32, line: LineControl) {
+        let divisor = baud_divisor(baud);
+
+        unsafe {
+            // Polling only, so keep UART interrupts off
+            self.interrupt_enable.write(0x00);
+
+            self.line_control.write(LINE_CONTROL_DLAB);
+            self.data.write(divisor as u8);
+            self.interrupt_enable.write((divisor >> 8) as u8);
+
+            // Writing the line control byte also clears DLAB again
+            self.line_control.write(line.bits());
+            self.fifo_control.write(FIFO_ENABLE_CLEAR_14);
+            self.modem_control.write(MODEM_DTR_RTS_OUT2);
+        }
+    }
+
+    fn line_status(&mut self) -> u8 {
+        unsafe { self.line_status.read() }
+    }
+
+    pub fn send(&mut self, byte: u8) {
+        // Wait until the transmit holding register is empty
+        while self.line_status() & LINE_STATUS_TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        unsafe { self.data.write(byte) };
+    }
+
+    pub fn receive(&mut self) -> Option<u8> {
+        if self.line_status() & LINE_STATUS_DATA_READY != 0 {
+            Some(unsafe { self.data.read() })
+        } else {
+            None
+        }
+    }
+}
+
+// The divisor register is 16 bits, rates too slow for it get the slowest one instead of wrapping
+fn baud_divisor(baud: u32) -> u16 {
+    (UART_CLOCK / baud.max(1)).clamp(1, u16::MAX as u32) as u16
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    pub static ref SERIAL1: Mutex<SerialPort> = {
+        let mut serial_port = SerialPort::new(ComPort::Com1);
+        serial_port.init(DEFAULT_BAUD, LineControl::new());
+        Mutex::new(serial_port)
+    };
+}
+
+// Macro definitions
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    // Same as vga_buffer::_print, the lock must not be held when an interrupt arrives
+    interrupts::without_interrupts(|| {
+        SERIAL1.lock().write_fmt(args).expect("Printing to serial failed");
+    });
+}
+
+// See vga_buffer::unlock_for_panic
+pub fn unlock_for_panic() {
+    if SERIAL1.try_lock().is_none() {
+        unsafe { SERIAL1.force_unlock() };
+    }
+}
+
+#[test_case]
+fn test_serial_println() {
+    crate::serial_println!("test_serial_println output");
+}
+
+#[test_case]
+fn test_line_control_bits() {
+    assert_eq!(LineControl::new().bits(), 0x03);
+    let line = LineControl {
+        data_bits: DataBits::Seven,
+        stop_bits: StopBits::Two,
+        parity: Parity::Even,
+    };
+    assert_eq!(line.bits(), 0b0001_1110);
+}
+
+#[test_case]
+fn test_baud_divisor() {
+    assert_eq!(baud_divisor(UART_CLOCK), 1);
+    assert_eq!(baud_divisor(9600), 12);
+    assert_eq!(baud_divisor(1), u16::MAX);
+    assert_eq!(baud_divisor(0), u16::MAX);
+    assert_eq!(baud_divisor(u32::MAX), 1);
+}
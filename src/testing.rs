@@ -0,0 +1,306 @@
+// testing.rs
+use core::any::type_name;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::qemu::{exit_qemu, QemuExitCode};
+use crate::vga_buffer::{Color, TEST_CONSOLE};
+
+/* Custom test framework
+
+    The test harness collects every #[test_case] and hands them to
+    test_runner. Each test reports its own name and result, so the
+    tests themselves only need to contain assertions.
+
+    Output goes to both the test console and COM1 so results can be read
+    on screen and captured by the host when QEMU runs headless.
+*/
+#[macro_export]
+macro_rules! test_print {
+    ($($arg:tt)*) => {{
+        $crate::console_print!($crate::vga_buffer::TEST_CONSOLE, $($arg)*);
+        $crate::serial_print!($($arg)*);
+    }};
+}
+
+#[macro_export]
+macro_rules! test_println {
+    () => ($crate::test_print!("\n"));
+    ($($arg:tt)*) => ($crate::test_print!("{}\n", format_args!($($arg)*)));
+}
+
+// Results stand out in color on screen, COM1 gets them as plain text
+fn print_status(color: Color, status: &str) {
+    crate::vga_buffer::_print_colored(TEST_CONSOLE, color, format_args!("{}", status));
+    crate::serial_print!("{}", status);
+}
+
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+static IGNORED: AtomicUsize = AtomicUsize::new(0);
+
+// The time stamp counter increments once per CPU cycle (or at a constant rate on newer CPUs)
+fn timestamp() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/* Anything the runner can execute
+
+    Plain #[test_case] functions get a default implementation that
+    expects them to return normally. TestDescriptor covers the tests
+    that should panic or be skipped.
+*/
+pub trait Testable: Sync {
+    fn name(&self) -> &'static str;
+
+    fn should_panic(&self) -> bool {
+        false
+    }
+
+    fn ignored(&self) -> bool {
+        false
+    }
+
+    fn run(&self);
+}
+
+impl<T> Testable for T
+where
+    T: Fn() + Sync,
+{
+    // type_name gives the full path of the function, e.g. monkos::serial::test_serial_println
+    fn name(&self) -> &'static str {
+        type_name::<T>()
+    }
+
+    fn run(&self) {
+        self();
+    }
+}
+
+// A registered test along with the markers the runner needs to know about
+pub struct TestDescriptor {
+    pub name: &'static str,
+    pub should_panic: bool,
+    pub ignore: bool,
+    pub test_fn: fn(),
+}
+
+impl Testable for TestDescriptor {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn should_panic(&self) -> bool {
+        self.should_panic
+    }
+
+    fn ignored(&self) -> bool {
+        self.ignore
+    }
+
+    fn run(&self) {
+        (self.test_fn)();
+    }
+}
+
+/* Registers a test with a marker attribute
+
+    kernel_test! {
+        #[should_panic]
+        fn rejects_bad_input() { ... }
+    }
+
+    The test body is wrapped in a module of the same name so the
+    descriptor can be a #[test_case] next to it. It has to be a const:
+    the harness hands the runner a slice of references to every test,
+    which only lives for 'static if none of them point at a static.
+*/
+#[macro_export]
+macro_rules! kernel_test {
+    (#[should_panic] fn $name:ident() $body:block) => {
+        $crate::kernel_test!(@descriptor $name, true, false, $body);
+    };
+    (#[ignore] fn $name:ident() $body:block) => {
+        $crate::kernel_test!(@descriptor $name, false, true, $body);
+    };
+    (@descriptor $name:ident, $should_panic:expr, $ignore:expr, $body:block) => {
+        #[cfg(test)]
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[test_case]
+            const TEST: $crate::testing::TestDescriptor = $crate::testing::TestDescriptor {
+                // Inside the wrapper module this is the path of the test itself
+                name: module_path!(),
+                should_panic: $should_panic,
+                ignore: $ignore,
+                test_fn: run,
+            };
+
+            fn run() $body
+        }
+    };
+}
+
+/* Runner state
+
+    With panic=abort there is no unwinding, so a panicking test never
+    returns to the runner. Instead the panic handler looks up which
+    test was running and calls back into the runner to carry on with
+    the next one.
+
+    The stack of the failed test is abandoned, so the runner carries on
+    from the top of a stack of its own. Otherwise every panic would leave
+    its frames behind and enough failures would overflow the stack. The
+    panic may also have come from an exception handler or inside
+    without_interrupts, so interrupts are turned back on first.
+*/
+static TESTS: Mutex<&'static [&'static dyn Testable]> = Mutex::new(&[]);
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+const RUNNER_STACK_SIZE: usize = 128 * 1024;
+
+static mut RUNNER_STACK: [u8; RUNNER_STACK_SIZE] = [0; RUNNER_STACK_SIZE];
+
+// Continues with test `next` on a fresh runner stack, whatever stack we are on now
+fn resume_from(next: usize) -> ! {
+    extern "C" fn resume(next: usize) -> ! {
+        x86_64::instructions::interrupts::enable();
+        run_from(next)
+    }
+
+    // The panic handler may itself be running on the runner stack, but nothing on it is used again
+    // The System V ABI wants the stack 16 byte aligned before the call
+    let stack_top = (core::ptr::addr_of_mut!(RUNNER_STACK) as usize + RUNNER_STACK_SIZE) & !0xF;
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {stack_top}",
+            "call {resume}",
+            stack_top = in(reg) stack_top,
+            resume = sym resume,
+            in("rdi") next,
+            options(noreturn),
+        )
+    }
+}
+
+fn print_summary() {
+    test_println!(
+        "test result: {} passed; {} failed; {} ignored",
+        PASSED.load(Ordering::SeqCst),
+        FAILED.load(Ordering::SeqCst),
+        IGNORED.load(Ordering::SeqCst),
+    );
+}
+
+fn finish() -> ! {
+    print_summary();
+    if FAILED.load(Ordering::SeqCst) == 0 {
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    crate::power::hlt_loop()
+}
+
+fn run_from(first: usize) -> ! {
+    let tests = *TESTS.lock();
+
+    for (index, test) in tests.iter().enumerate().skip(first) {
+        CURRENT.store(index, Ordering::SeqCst);
+        test_print!("{}...\t", test.name());
+
+        if test.ignored() {
+            IGNORED.fetch_add(1, Ordering::SeqCst);
+            print_status(Color::LightCyan, "[ignored]");
+            test_println!();
+            continue;
+        }
+
+        RUNNING.store(true, Ordering::SeqCst);
+        let start = timestamp();
+        test.run();
+        let cycles = timestamp().wrapping_sub(start);
+        RUNNING.store(false, Ordering::SeqCst);
+
+        if test.should_panic() {
+            FAILED.fetch_add(1, Ordering::SeqCst);
+            print_status(Color::LightRed, "[failed]");
+            test_println!("\n");
+            test_println!("Error: test did not panic\n");
+        } else {
+            PASSED.fetch_add(1, Ordering::SeqCst);
+            print_status(Color::LightGreen, "[ok]");
+            test_println!(" ({} cycles)", cycles);
+        }
+    }
+
+    finish()
+}
+
+pub fn test_runner(tests: &'static [&'static dyn Testable]) {
+    crate::vga_buffer::switch_to(TEST_CONSOLE);
+    test_println!("Running {} tests", tests.len());
+    *TESTS.lock() = tests;
+    run_from(0)
+}
+
+// Under test a panic either completes a should_panic test or fails the current one
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    crate::unlock_consoles();
+
+    if RUNNING.swap(false, Ordering::SeqCst) {
+        let index = CURRENT.load(Ordering::SeqCst);
+        let tests = *TESTS.lock();
+
+        if tests[index].should_panic() {
+            PASSED.fetch_add(1, Ordering::SeqCst);
+            print_status(Color::LightGreen, "[ok]");
+            test_println!();
+        } else {
+            FAILED.fetch_add(1, Ordering::SeqCst);
+            print_status(Color::LightRed, "[failed]");
+            test_println!("\n");
+            test_println!("Error: {}\n", info);
+        }
+        resume_from(index + 1)
+    }
+
+    // The panic happened outside of a test, so there is nothing to resume
+    FAILED.fetch_add(1, Ordering::SeqCst);
+    print_status(Color::LightRed, "[failed]");
+    test_println!("\n");
+    test_println!("Error: {}\n", info);
+    finish()
+}
+
+kernel_test! {
+    #[ignore]
+    fn ignored_test_is_skipped() {
+        panic!("ignored test was run");
+    }
+}
+
+kernel_test! {
+    #[should_panic]
+    fn should_panic_test_passes_on_panic() {
+        panic!("expected panic");
+    }
+}
+
+kernel_test! {
+    #[should_panic]
+    fn panic_with_interrupts_disabled() {
+        x86_64::instructions::interrupts::without_interrupts(|| panic!("expected panic"));
+    }
+}
+
+// Runs right after the test above, on the stack the runner resumed on
+#[test_case]
+fn test_interrupts_enabled_after_panic() {
+    assert!(x86_64::instructions::interrupts::are_enabled());
+}
@@ -1,6 +1,18 @@
 // vga_buffer.rs
 use volatile::Volatile;
 use core::fmt;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub mod cursor;
+pub mod attribute;
+pub mod ansi;
+pub mod utf8;
+pub mod cp437;
+pub mod scrollback;
+
+pub use cursor::CursorShape;
+use scrollback::{Line, Scrollback};
 
 // Disable the compiler warnings (For unused colors)
 #[allow(dead_code)]
@@ -27,36 +39,136 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    // Only the low 4 bits are used, so every value maps to a color
+    pub fn from_index(index: u8) -> Color {
+        match index & 0x0F {
+            0 => Color::Black,
+            1 => Color::Blue,
+            2 => Color::Green,
+            3 => Color::Cyan,
+            4 => Color::Red,
+            5 => Color::Magenta,
+            6 => Color::Brown,
+            7 => Color::LightGray,
+            8 => Color::DarkGray,
+            9 => Color::LightBlue,
+            10 => Color::LightGreen,
+            11 => Color::LightCyan,
+            12 => Color::LightRed,
+            13 => Color::Pink,
+            14 => Color::Yellow,
+            _ => Color::White,
+        }
+    }
+
+    /* ANSI numbers its 8 colors differently to VGA
+
+       ANSI | 0     1   2     3      4    5       6    7
+       VGA  | Black Red Green Brown  Blue Magenta Cyan LightGray
+    */
+    pub fn from_ansi(index: u8) -> Color {
+        const ANSI_TO_VGA: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+        Color::from_index(ANSI_TO_VGA[(index & 0x07) as usize] | (index & 0x08))
+    }
+
+    // The bright version of one of the first 8 colors
+    pub fn bright(self) -> Color {
+        Color::from_index(self as u8 | 0x08)
+    }
+}
+
 use spin::Mutex;
 use lazy_static::lazy_static;
 
 
+/* Virtual consoles
+
+    Every console draws into its own screen in RAM. Only the active one
+    is also written to the VGA buffer, switching redraws the hardware
+    from the newly active console's screen.
+
+    Console | Used for
+    0       | Kernel log, what print! writes to
+    1       | Shell
+    2       | Test output
+    3-5     | Free
+*/
+pub const CONSOLE_COUNT: usize = 6;
+pub const LOG_CONSOLE: usize = 0;
+pub const SHELL_CONSOLE: usize = 1;
+pub const TEST_CONSOLE: usize = 2;
+
+static ACTIVE_CONSOLE: AtomicUsize = AtomicUsize::new(LOG_CONSOLE);
+
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    pub static ref CONSOLES: [Mutex<Writer>; CONSOLE_COUNT] =
+        core::array::from_fn(|index| Mutex::new(Writer::new(index)));
+    // The kernel log console, kept under its old name
+    pub static ref WRITER: &'static Mutex<Writer> = &CONSOLES[LOG_CONSOLE];
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 // Ensures that the Color code has the exact same layout as u8
 #[repr(transparent)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 /* Constructing ColorCode byte
 
     Bits | Value
     0-3  | Foreground color
     4-6  | Background color
-    7    | Blink
+    7    | Blink, or the bright background bit with blinking turned off
 
 */
 impl ColorCode {
     // Construct the color code
-    fn new(foreground: Color, background: Color) -> ColorCode {
+    pub fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    pub fn with_blink(self, blink: bool) -> ColorCode {
+        if blink {
+            ColorCode(self.0 | 0x80)
+        } else {
+            ColorCode(self.0 & 0x7F)
+        }
+    }
+
+    pub fn foreground(self) -> Color {
+        Color::from_index(self.0)
+    }
+
+    pub fn background(self) -> Color {
+        Color::from_index(self.0 >> 4)
+    }
+}
+
+/* Blinking or bright backgrounds
+
+    Bit 7 of a color byte either makes the character blink (the default)
+    or selects one of the 8 bright backgrounds. Which one is a setting of
+    the attribute controller and applies to the whole screen at once.
+*/
+static BLINK_ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn blink_enabled() -> bool {
+    BLINK_ENABLED.load(Ordering::SeqCst)
+}
+
+// Text already on screen keeps its color byte, so it may change meaning
+pub fn set_blink_enabled(enabled: bool) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        attribute::set_blink(enabled);
+        BLINK_ENABLED.store(enabled, Ordering::SeqCst);
+        for console in CONSOLES.iter() {
+            let mut writer = console.lock();
+            let attributes = writer.attributes;
+            writer.set_attributes(attributes);
+        }
+    });
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -66,13 +178,25 @@ impl ColorCode {
    laid out like a C struct which guarantees the correct ordering
 */
 #[repr(C)]
-struct ScreenChar {
-    ascii_character: u8,
-    color_code: ColorCode,
+pub struct ScreenChar {
+    pub ascii_character: u8,
+    pub color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+// Used to initialise storage, the writer always blanks with its current color
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: ColorCode(0x07),
+};
+
+// Kept out of the consoles themselves so building them doesn't need a large stack
+static mut SCREENS: [[Line; BUFFER_HEIGHT]; CONSOLE_COUNT] = [[[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT]; CONSOLE_COUNT];
+static mut HISTORY: [[Line; scrollback::DEFAULT_CAPACITY]; CONSOLE_COUNT] =
+    [[[BLANK; BUFFER_WIDTH]; scrollback::DEFAULT_CAPACITY]; CONSOLE_COUNT];
+
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
+pub const DEFAULT_TAB_WIDTH: usize = 8;
 
 /*
     Because the compiler doesnt know were accessing the VGA buffer memory
@@ -86,28 +210,196 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+// Only the active console may write here
+fn hardware() -> &'static mut Buffer {
+    unsafe { &mut *(0xb8000 as *mut Buffer) }
+}
+
+/* When the screen in RAM is copied to the VGA buffer
+
+    Writes always land in the console's own screen first and only the
+    rows (and columns within them) that changed are copied over. MMIO is
+    slow, so a burst of output that scrolls many times only pays for the
+    final picture.
+
+    Policy       | Copied when
+    WriteThrough | Every character, as soon as it is written
+    EveryWrite   | At the end of each write, clear or move
+    Manual       | Only on flush(), switching consoles or scrolling the view
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    WriteThrough,
+    #[default]
+    EveryWrite,
+    Manual,
+}
+
+// Columns start..end of a row differ from the hardware, empty when start >= end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DirtySpan {
+    start: usize,
+    end: usize,
+}
+
+impl DirtySpan {
+    const CLEAN: DirtySpan = DirtySpan { start: BUFFER_WIDTH, end: 0 };
+
+    fn is_clean(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
+// Text attributes as set by escape sequences or the color API, turned into a ColorCode when they change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Attributes {
+    foreground: Color,
+    background: Color,
+    bold: bool,
+    reverse: bool,
+    blink: bool,
+}
+
+impl Attributes {
+    const fn new(foreground: Color, background: Color) -> Attributes {
+        Attributes { foreground, background, bold: false, reverse: false, blink: false }
+    }
+
+    // VGA has no bold font, so bold is shown as the bright version of the color
+    fn color_code(&self) -> ColorCode {
+        let foreground = if self.bold { self.foreground.bright() } else { self.foreground };
+        let (foreground, background) = if self.reverse {
+            (self.background, foreground)
+        } else {
+            (foreground, self.background)
+        };
+
+        if blink_enabled() {
+            // The top background bit means blink, so only the first 8 colors can be backgrounds
+            ColorCode::new(foreground, Color::from_index(background as u8 & 0x07)).with_blink(self.blink)
+        } else {
+            ColorCode::new(foreground, background)
+        }
+    }
+}
+
+// How many colors push_color can save, deeper pushes are counted but not restored
+const COLOR_STACK_DEPTH: usize = 8;
+
+/* Writer position
+
+    Text is written at (row_position, column_position). Rows above
+    start_row are never written or scrolled by normal output, which
+    leaves them free for things like a status bar.
+
+    With row tracking on, new lines move down the screen until the
+    last row and only then scroll. With it off every new line scrolls,
+    so output stays on one row (the last one by default) like a log.
+*/
 pub struct Writer {
     column_position: usize,
+    row_position: usize,
+    start_row: usize,
+    row_tracking: bool,
+    // Always kept in sync with `attributes`
     color_code: ColorCode,
-    // the 'static lifetime specifies that the reference is valid for the entire runtime
-    buffer: &'static mut Buffer,
+    attributes: Attributes,
+    // What SGR 0 and ESC c go back to
+    default_attributes: Attributes,
+    color_stack: [Attributes; COLOR_STACK_DEPTH],
+    color_depth: usize,
+    decoder: utf8::Utf8Decoder,
+    parser: ansi::Parser,
+    saved_position: (usize, usize),
+    scrollback: Scrollback,
+    // How many lines the view is scrolled back into history, 0 shows live output
+    view_offset: usize,
+    // Whether this console is the one on screen
+    active: bool,
+    // None while the cursor is hidden
+    cursor_shape: Option<CursorShape>,
+    // The live screen, the 'static lifetime specifies that it is valid for the entire runtime
+    screen: &'static mut [Line; BUFFER_HEIGHT],
+    // What still has to be copied to the hardware, only tracked while active
+    dirty: [DirtySpan; BUFFER_HEIGHT],
+    flush_policy: FlushPolicy,
+    // Columns between tab stops
+    tab_width: usize,
+    // Whether backspace also erases the character it moves back over
+    destructive_backspace: bool,
+    // Set during write_string_at, output then never leaves the current row
+    clipped: bool,
 }
 
 
 impl Writer {
+    // Only called once per console, by CONSOLES
+    fn new(index: usize) -> Writer {
+        // Safe because each console takes only its own slot
+        let (screen, history) = unsafe {
+            (&mut (*addr_of_mut!(SCREENS))[index], &mut (*addr_of_mut!(HISTORY))[index])
+        };
+        let mut writer = Writer {
+            column_position: 0,
+            row_position: 0,
+            start_row: 0,
+            row_tracking: true,
+            color_code: ColorCode::new(Color::Yellow, Color::Black),
+            attributes: Attributes::new(Color::Yellow, Color::Black),
+            default_attributes: Attributes::new(Color::Yellow, Color::Black),
+            color_stack: [Attributes::new(Color::Yellow, Color::Black); COLOR_STACK_DEPTH],
+            color_depth: 0,
+            decoder: utf8::Utf8Decoder::new(),
+            parser: ansi::Parser::new(),
+            saved_position: (0, 0),
+            scrollback: Scrollback::new(history),
+            view_offset: 0,
+            active: index == ACTIVE_CONSOLE.load(Ordering::SeqCst),
+            cursor_shape: Some(CursorShape::Underline),
+            screen,
+            dirty: [DirtySpan::CLEAN; BUFFER_HEIGHT],
+            flush_policy: FlushPolicy::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            destructive_backspace: false,
+            clipped: false,
+        };
+        // Writing starts at the top, so clear whatever the bootloader left there
+        writer.clear_screen();
+        writer
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Control characters are carried out, every other byte is shown as its code page 437 glyph
     pub fn write_byte(&mut self, byte: u8) {
+        self.scroll_to_live();
+        if !self.control(byte as char) {
+            self.put_byte(byte);
+        }
+        self.finish_write();
+    }
+
+    // Writes without moving the hardware cursor, so a whole string only moves it once
+    fn put_byte(&mut self, byte: u8) {
         match byte {
+            // Nothing after the newline shows up
+            b'\n' if self.clipped => self.column_position = BUFFER_WIDTH,
             b'\n' => self.new_line(),
             byte  => {
                 if self.column_position >= BUFFER_WIDTH {
+                    if self.clipped {
+                        return;
+                    }
                     self.new_line();
                 }
 
-                let row = BUFFER_HEIGHT - 1;
+                let row = self.row_position;
                 let col = self.column_position;
 
                 let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
+                self.put_char(row, col, ScreenChar {
                     ascii_character: byte,
                     color_code,
                 });
@@ -116,28 +408,589 @@ impl Writer {
         }
     }
 
+    // Interprets escape sequences, unlike write_byte which always puts the byte on screen
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0xfe), // unprintable byte ■
+        self.write_bytes(s.as_bytes());
+    }
+
+    /* Writes UTF-8 text
+
+       Bytes are decoded into characters, which go through the escape
+       sequence parser and are then shown with their code page 437 glyph.
+       A character split across two calls is still put together.
+    */
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.scroll_to_live();
+        for &byte in bytes {
+            let mut decoded = None;
+            self.decoder.push(byte, |character| {
+                // A broken sequence can produce a replacement and the next character at once
+                match decoded {
+                    None => decoded = Some((character, None)),
+                    Some((first, _)) => decoded = Some((first, Some(character))),
+                }
+            });
+            if let Some((first, second)) = decoded {
+                self.write_char(first);
+                if let Some(second) = second {
+                    self.write_char(second);
+                }
             }
         }
+        self.finish_write();
     }
 
-    pub fn new_line(&mut self) { 
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+    fn write_char(&mut self, character: char) {
+        if let Some(action) = self.parser.advance(character) {
+            self.perform(action);
+        }
+    }
+
+    fn perform(&mut self, action: ansi::Action) {
+        use ansi::Action;
+
+        // A clipped write keeps its colors, but anything that moves around or clears is dropped
+        if self.clipped {
+            match action {
+                Action::Print(_) | Action::Control(_) => {}
+                Action::Csi(csi) if csi.final_byte == b'm' && !csi.private => {}
+                _ => return,
             }
         }
 
-        self.clear_row(BUFFER_HEIGHT - 1);
+        match action {
+            Action::Print(character) => self.put_byte(cp437::glyph(character)),
+            Action::Control(character) => {
+                if !self.control(character) {
+                    self.put_byte(cp437::REPLACEMENT_GLYPH);
+                }
+            }
+            Action::SaveCursor => self.saved_position = self.position(),
+            Action::RestoreCursor => {
+                let (row, col) = self.saved_position;
+                self.set_position(row, col);
+            }
+            Action::Reset => {
+                self.set_attributes(self.default_attributes);
+                self.clear_screen();
+            }
+            Action::Csi(csi) => self.perform_csi(&csi),
+        }
+    }
+
+    /* Control characters
+
+       Character | Meaning
+       \n        | New line
+       \r        | Back to the start of the line
+       \t        | On to the next tab stop, never past the last column
+       BS (0x08) | Back one column, or to the end of the previous line from the first
+       FF (0x0C) | Clear the screen
+       BEL (0x07)| Ring the bell handler
+
+       Returns false for control characters without a meaning here.
+    */
+    fn control(&mut self, character: char) -> bool {
+        match character {
+            '\n' => self.put_byte(b'\n'),
+            '\r' => self.column_position = 0,
+            '\t' => {
+                let next = (self.column_position / self.tab_width + 1) * self.tab_width;
+                // Right after the last column the position already waits to wrap, leave it there
+                self.column_position = next.min(BUFFER_WIDTH - 1).max(self.column_position);
+            }
+            '\u{08}' => self.backspace(),
+            '\u{0C}' if self.clipped => {}
+            '\u{0C}' => self.clear_screen(),
+            '\u{07}' => ring_bell(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn backspace(&mut self) {
+        if self.column_position > 0 {
+            self.column_position = self.column_position.min(BUFFER_WIDTH) - 1;
+        } else if self.row_position > self.start_row && !self.clipped {
+            self.row_position -= 1;
+            self.column_position = BUFFER_WIDTH - 1;
+        } else {
+            return;
+        }
+
+        if self.destructive_backspace {
+            self.clear_columns(self.row_position, self.column_position, self.column_position + 1);
+        }
+    }
+
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tab_width = width.clamp(1, BUFFER_WIDTH);
+    }
+
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    // A line editor wants backspace to erase, a terminal by default only moves back
+    pub fn set_destructive_backspace(&mut self, enabled: bool) {
+        self.destructive_backspace = enabled;
+    }
+
+    /* Control sequences
+
+       Final | Meaning
+       A B   | Cursor up / down
+       C D   | Cursor forward / back
+       E F   | Start of the next / previous line
+       G     | Column (1 based)
+       H f   | Row;column (1 based)
+       J     | Erase in display (0 to end, 1 to start, 2 all)
+       K     | Erase in line (0 to end, 1 to start, 2 all)
+       m     | Select graphic rendition (colors)
+       s u   | Save / restore cursor
+       ?25h  | Show / hide (l) the cursor
+    */
+    fn perform_csi(&mut self, csi: &ansi::CsiSequence) {
+        let (row, col) = self.position();
+        let count = csi.param(0, 1) as usize;
+
+        if csi.private {
+            if csi.param(0, 0) == 25 {
+                match csi.final_byte {
+                    b'h' => self.enable_cursor(CursorShape::Underline),
+                    b'l' => self.disable_cursor(),
+                    _ => {}
+                }
+            }
+            return;
+        }
+
+        match csi.final_byte {
+            b'A' => self.set_position(row.saturating_sub(count), col),
+            b'B' => self.set_position(row + count, col),
+            b'C' => self.set_position(row, col + count),
+            b'D' => self.set_position(row, col.saturating_sub(count)),
+            b'E' => self.set_position(row + count, 0),
+            b'F' => self.set_position(row.saturating_sub(count), 0),
+            b'G' => self.set_position(row, count - 1),
+            b'H' | b'f' => {
+                let col = csi.param(1, 1) as usize;
+                self.set_position(count - 1, col - 1);
+            }
+            b'J' => self.erase_in_display(csi.param(0, 0)),
+            b'K' => self.erase_in_line(csi.param(0, 0)),
+            b'm' => self.select_graphic_rendition(csi.params()),
+            b's' => self.saved_position = self.position(),
+            b'u' => {
+                let (row, col) = self.saved_position;
+                self.set_position(row, col);
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_in_display(&mut self, mode: u16) {
+        let (row, col) = self.position();
+        match mode {
+            0 => {
+                self.clear_to_end_of_line();
+                for row in row + 1..BUFFER_HEIGHT {
+                    self.clear_row(row);
+                }
+            }
+            1 => {
+                for row in self.start_row..row {
+                    self.clear_row(row);
+                }
+                self.clear_columns(row, 0, col + 1);
+            }
+            // The cursor stays where it is, unlike clear_screen
+            _ => {
+                for row in self.start_row..BUFFER_HEIGHT {
+                    self.clear_row(row);
+                }
+            }
+        }
+    }
+
+    fn erase_in_line(&mut self, mode: u16) {
+        let (row, col) = self.position();
+        match mode {
+            0 => self.clear_to_end_of_line(),
+            1 => self.clear_columns(row, 0, col + 1),
+            _ => self.clear_row(row),
+        }
+    }
+
+    // An empty parameter list means reset, like a single 0
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        let mut attributes = self.attributes;
+        for &param in params.iter().chain(params.is_empty().then_some(&0)) {
+            match param {
+                0 => attributes = self.default_attributes,
+                1 => attributes.bold = true,
+                22 => attributes.bold = false,
+                5 => attributes.blink = true,
+                25 => attributes.blink = false,
+                7 => attributes.reverse = true,
+                27 => attributes.reverse = false,
+                30..=37 => attributes.foreground = Color::from_ansi((param - 30) as u8),
+                39 => attributes.foreground = self.default_attributes.foreground,
+                40..=47 => attributes.background = Color::from_ansi((param - 40) as u8),
+                49 => attributes.background = self.default_attributes.background,
+                90..=97 => attributes.foreground = Color::from_ansi((param - 90) as u8).bright(),
+                // Only shown bright with blinking turned off, see set_blink_enabled
+                100..=107 => attributes.background = Color::from_ansi((param - 100) as u8).bright(),
+                _ => {}
+            }
+        }
+        self.set_attributes(attributes);
+    }
+
+    fn set_attributes(&mut self, attributes: Attributes) {
+        self.attributes = attributes;
+        self.color_code = attributes.color_code();
+    }
+
+    /* Colors
+
+       Setting a color replaces whatever escape sequences had set, bold
+       and reverse included. Blinking only shows while the attribute
+       controller is in blink mode, see set_blink_enabled.
+    */
+    pub fn color_code(&self) -> ColorCode {
+        self.color_code
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.attributes.foreground
+    }
+
+    pub fn background(&self) -> Color {
+        self.attributes.background
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        let blink = self.attributes.blink;
+        self.set_attributes(Attributes { blink, ..Attributes::new(foreground, background) });
+    }
+
+    pub fn set_foreground(&mut self, foreground: Color) {
+        self.set_color(foreground, self.attributes.background);
+    }
+
+    pub fn set_background(&mut self, background: Color) {
+        self.set_color(self.attributes.foreground, background);
+    }
+
+    pub fn set_blink(&mut self, blink: bool) {
+        self.set_attributes(Attributes { blink, ..self.attributes });
+    }
+
+    // The colors reset_color, SGR 0 and ESC c go back to, also made the current ones
+    pub fn set_default_color(&mut self, foreground: Color, background: Color) {
+        self.default_attributes = Attributes::new(foreground, background);
+        self.set_attributes(self.default_attributes);
+    }
+
+    pub fn reset_color(&mut self) {
+        self.set_attributes(self.default_attributes);
+    }
+
+    // Saves the current colors and switches to new ones until the matching pop_color
+    pub fn push_color(&mut self, foreground: Color, background: Color) {
+        if self.color_depth < COLOR_STACK_DEPTH {
+            self.color_stack[self.color_depth] = self.attributes;
+        }
+        self.color_depth += 1;
+        self.set_color(foreground, background);
+    }
+
+    // Returns false if there was nothing to pop
+    pub fn pop_color(&mut self) -> bool {
+        if self.color_depth == 0 {
+            return false;
+        }
+        self.color_depth -= 1;
+        if self.color_depth < COLOR_STACK_DEPTH {
+            self.set_attributes(self.color_stack[self.color_depth]);
+        }
+        true
+    }
+
+    fn put_char(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.screen[row][col] = character;
+        self.mark_dirty(row, col, col + 1);
+        if self.flush_policy == FlushPolicy::WriteThrough {
+            self.flush();
+        }
+    }
+
+    fn mark_dirty(&mut self, row: usize, start: usize, end: usize) {
+        if !self.active {
+            return;
+        }
+        let span = &mut self.dirty[row];
+        span.start = span.start.min(start);
+        span.end = span.end.max(end);
+    }
+
+    // Copies the changed parts of the screen to the VGA buffer
+    pub fn flush(&mut self) {
+        if !self.active {
+            return;
+        }
+        let buffer = hardware();
+        for row in 0..BUFFER_HEIGHT {
+            let span = core::mem::replace(&mut self.dirty[row], DirtySpan::CLEAN);
+            if span.is_clean() {
+                continue;
+            }
+            let line = &self.displayed_line(row)[span.start..span.end];
+            for (col, character) in (span.start..).zip(line) {
+                buffer.chars[row][col].write(*character);
+            }
+        }
+    }
+
+    pub fn flush_policy(&self) -> FlushPolicy {
+        self.flush_policy
+    }
+
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+        if policy != FlushPolicy::Manual {
+            self.flush();
+        }
+    }
+
+    // Called at the end of every public operation that changes the screen
+    fn finish_write(&mut self) {
+        if self.flush_policy != FlushPolicy::Manual {
+            self.flush();
+        }
+        self.update_cursor();
+    }
+
+    // Puts the blinking cursor where the next character will go
+    pub fn update_cursor(&self) {
+        if !self.active {
+            return;
+        }
+        // After the last column the next character wraps, so keep the cursor on the line until it does
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        cursor::set_position(self.row_position, col, BUFFER_WIDTH);
+    }
+
+    // Each console remembers its own cursor, it shows up when the console does
+    pub fn enable_cursor(&mut self, shape: CursorShape) {
+        self.cursor_shape = Some(shape);
+        if self.active {
+            cursor::enable(shape);
+        }
+        self.update_cursor();
+    }
+
+    pub fn disable_cursor(&mut self) {
+        self.cursor_shape = None;
+        if self.active {
+            cursor::disable();
+        }
+    }
+
+    // Puts this console on screen, the previously active one must already be deactivated
+    fn activate(&mut self) {
+        self.active = true;
+        // Whatever the hardware shows belongs to the previous console, so everything is redrawn
+        for row in 0..BUFFER_HEIGHT {
+            self.draw_row(row);
+        }
+        self.flush();
+        match self.cursor_shape {
+            Some(shape) => cursor::enable(shape),
+            None => cursor::disable(),
+        }
+        self.update_cursor();
+    }
+
+    fn deactivate(&mut self) {
+        self.flush();
+        self.active = false;
+        self.dirty = [DirtySpan::CLEAN; BUFFER_HEIGHT];
+    }
+
+    pub fn new_line(&mut self) {
+        if self.row_tracking && self.row_position < BUFFER_HEIGHT - 1 {
+            self.row_position += 1;
+        } else {
+            self.scroll_up();
+        }
         self.column_position = 0;
     }
 
+    // Moves the rows below start_row up by one, the rows above stay where they are
+    fn scroll_up(&mut self) {
+        self.scrollback.push(&self.screen[self.start_row]);
+
+        self.screen.copy_within(self.start_row + 1..BUFFER_HEIGHT, self.start_row);
+        for row in self.start_row..BUFFER_HEIGHT - 1 {
+            self.draw_row(row);
+        }
+        self.clear_row(BUFFER_HEIGHT - 1);
+    }
+
+    // Positions are clamped to the screen, rows above start_row can only be reached with write_string_at
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.scroll_to_live();
+        self.row_position = row.clamp(self.start_row, BUFFER_HEIGHT - 1);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+        self.finish_write();
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    /* Writes at a fixed place without moving the writer
+
+       The text is clipped to that row: it never wraps or scrolls, and
+       whatever comes after the last column or a newline is dropped. That
+       keeps it safe for rows above start_row, like a status bar.
+    */
+    pub fn write_string_at(&mut self, row: usize, col: usize, s: &str) {
+        self.scroll_to_live();
+        let saved = (self.row_position, self.column_position);
+        self.row_position = row.min(BUFFER_HEIGHT - 1);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+        self.clipped = true;
+        self.write_string(s);
+        self.clipped = false;
+        (self.row_position, self.column_position) = saved;
+        self.finish_write();
+    }
+
+    /* Sets the first row normal output uses and moves there
+
+       Everything from the new start row down is cleared, the rows above
+       keep whatever they showed.
+    */
+    pub fn set_start_row(&mut self, row: usize) {
+        self.scroll_to_live();
+        self.start_row = row.min(BUFFER_HEIGHT - 1);
+        for row in self.start_row..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+        self.set_position(self.start_row, 0);
+    }
+
+    pub fn set_row_tracking(&mut self, enabled: bool) {
+        self.row_tracking = enabled;
+    }
+
+    // Clears everything below the start row and goes back to its beginning
+    pub fn clear_screen(&mut self) {
+        self.scroll_to_live();
+        for row in self.start_row..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+        self.set_position(self.start_row, 0);
+    }
+
+    pub fn clear_to_end_of_line(&mut self) {
+        self.scroll_to_live();
+        self.clear_columns(self.row_position, self.column_position, BUFFER_WIDTH);
+        self.finish_write();
+    }
+
+    fn clear_columns(&mut self, row: usize, start: usize, end: usize) {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+
+        for col in start..end.min(BUFFER_WIDTH) {
+            self.put_char(row, col, blank);
+        }
+    }
+
+    /* Scrollback view
+
+       While scrolled back, the rows from start_row down show a window
+       into history followed by the live rows. Output keeps going to the
+       live screen in the meantime, but any public write first snaps the
+       view back to live output.
+    */
+    pub fn scroll_back(&mut self, lines: usize) {
+        let offset = (self.view_offset + lines).min(self.scrollback.len());
+        self.show_history(offset);
+    }
+
+    pub fn scroll_forward(&mut self, lines: usize) {
+        self.show_history(self.view_offset.saturating_sub(lines));
+    }
+
+    // Shift+PageUp and Shift+PageDown move by a screen, keeping a line for context
+    pub fn page_up(&mut self) {
+        self.scroll_back(self.scrolling_rows() - 1);
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_forward(self.scrolling_rows() - 1);
+    }
+
+    pub fn scroll_to_live(&mut self) {
+        self.show_history(0);
+    }
+
+    pub fn view_offset(&self) -> usize {
+        self.view_offset
+    }
+
+    pub fn scrollback(&self) -> &Scrollback {
+        &self.scrollback
+    }
+
+    pub fn set_scrollback_limit(&mut self, lines: usize) {
+        self.scroll_to_live();
+        self.scrollback.set_limit(lines);
+    }
+
+    fn scrolling_rows(&self) -> usize {
+        BUFFER_HEIGHT - self.start_row
+    }
+
+    fn show_history(&mut self, offset: usize) {
+        if offset == self.view_offset {
+            return;
+        }
+
+        self.view_offset = offset;
+        for row in self.start_row..BUFFER_HEIGHT {
+            self.draw_row(row);
+        }
+        // Scrolling through history is asked for by the user, so it shows up right away
+        self.flush();
+    }
+
+    // What a row shows right now: line `index` of history followed by the live rows
+    fn displayed_line(&self, row: usize) -> &Line {
+        if row < self.start_row || self.view_offset == 0 {
+            return &self.screen[row];
+        }
+
+        let history = self.scrollback.len();
+        let index = history - self.view_offset + (row - self.start_row);
+        match self.scrollback.get(index) {
+            Some(line) => line,
+            None => &self.screen[self.start_row + index - history],
+        }
+    }
+
+    // The whole row changed, e.g. after scrolling
+    fn draw_row(&mut self, row: usize) {
+        self.mark_dirty(row, 0, BUFFER_WIDTH);
+    }
+
     fn clear_row(&mut self, row: usize) {
         let blank = ScreenChar {
             ascii_character: b' ',
@@ -145,7 +998,7 @@ impl Writer {
         };
 
         for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+            self.put_char(row, col, blank);
         }
     }
 }
@@ -170,8 +1023,719 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+// Like print!, but to the given virtual console
+#[macro_export]
+macro_rules! console_print {
+    ($console:expr, $($arg:tt)*) => ($crate::vga_buffer::_print_to($console, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! console_println {
+    ($console:expr) => ($crate::console_print!($console, "\n"));
+    ($console:expr, $($arg:tt)*) => ($crate::console_print!($console, "{}\n", format_args!($($arg)*)));
+}
+
+// Prints to the kernel log in one foreground color, then goes back to the previous colors
+#[macro_export]
+macro_rules! print_colored {
+    ($color:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_print_colored($crate::vga_buffer::LOG_CONSOLE, $color, format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! println_colored {
+    ($color:expr) => ($crate::print_colored!($color, "\n"));
+    ($color:expr, $($arg:tt)*) => ($crate::print_colored!($color, "{}\n", format_args!($($arg)*)));
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    _print_to(LOG_CONSOLE, args);
+}
+
+#[doc(hidden)]
+pub fn _print_to(index: usize, args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    // An interrupt handler that prints while we hold the lock would spin forever
+    interrupts::without_interrupts(|| {
+        console(index).lock().write_fmt(args).unwrap();
+    });
+}
+
+#[doc(hidden)]
+pub fn _print_colored(index: usize, foreground: Color, args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = console(index).lock();
+        let background = writer.background();
+        writer.push_color(foreground, background);
+        writer.write_fmt(args).unwrap();
+        writer.pop_color();
+    });
+}
+
+/* Scoped colors
+
+    Everything printed to the console while the guard is alive uses its
+    colors, dropping it pops them again.
+
+        let _error = ColorGuard::new(LOG_CONSOLE, Color::LightRed, Color::Black);
+        println!("something went wrong");
+*/
+pub struct ColorGuard {
+    console: usize,
+}
+
+impl ColorGuard {
+    pub fn new(console: usize, foreground: Color, background: Color) -> ColorGuard {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            self::console(console).lock().push_color(foreground, background);
+        });
+        ColorGuard { console }
+    }
+}
+
+impl Drop for ColorGuard {
+    fn drop(&mut self) {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            console(self.console).lock().pop_color();
+        });
+    }
+}
+
+pub fn console(index: usize) -> &'static Mutex<Writer> {
+    &CONSOLES[index]
+}
+
+/* Bell
+
+    BEL asks for attention, what that means is left to whoever installs
+    a handler (the kernel uses the PC speaker). The handler runs with
+    the console locked and interrupts disabled, so it must not print.
+*/
+pub type BellHandler = fn();
+
+static BELL_HANDLER: Mutex<Option<BellHandler>> = Mutex::new(None);
+
+pub fn set_bell_handler(handler: Option<BellHandler>) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        *BELL_HANDLER.lock() = handler;
+    });
+}
+
+fn ring_bell() {
+    // Copied out so the handler may replace itself
+    let handler = *BELL_HANDLER.lock();
+    if let Some(handler) = handler {
+        handler();
+    }
+}
+
+pub fn active_console() -> usize {
+    ACTIVE_CONSOLE.load(Ordering::SeqCst)
+}
+
+// Shows another console, the one that was on screen keeps running in the background
+pub fn switch_to(index: usize) {
+    use x86_64::instructions::interrupts;
+
+    assert!(index < CONSOLE_COUNT, "there is no console {}", index);
+    interrupts::without_interrupts(|| {
+        let previous = ACTIVE_CONSOLE.swap(index, Ordering::SeqCst);
+        if previous != index {
+            CONSOLES[previous].lock().deactivate();
+            CONSOLES[index].lock().activate();
+        }
+    });
+}
+
+/* Panic-time access to the writer
+
+    If the kernel panics (or hits a fatal exception) while a console is
+    locked, the code holding the lock will never run again and the panic
+    message could never be shown. Only call this on paths that never
+    return to the interrupted code.
+*/
+pub fn unlock_for_panic() {
+    for console in CONSOLES.iter() {
+        if console.try_lock().is_none() {
+            unsafe { console.force_unlock() };
+        }
+        // Nothing may be left waiting for a flush that never comes
+        let mut writer = console.lock();
+        if writer.flush_policy() == FlushPolicy::Manual {
+            writer.set_flush_policy(FlushPolicy::EveryWrite);
+        }
+    }
+}
+
+#[test_case]
+fn test_println_after_unlock_for_panic() {
+    // Simulate a panic that happened while the writer was locked
+    core::mem::forget(WRITER.lock());
+    unlock_for_panic();
+    println!("test_println_after_unlock_for_panic output");
+}
+
+#[test_case]
+fn test_println_with_interrupts_enabled() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::enable();
+    for _ in 0..100 {
+        println!("test_println_with_interrupts_enabled output");
+    }
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn test_cursor_follows_writes() {
+    use x86_64::instructions::interrupts;
+
+    // The hardware cursor belongs to whichever console is on screen
+    interrupts::without_interrupts(|| {
+        let original = active_console();
+        switch_to(5);
+        let mut writer = console(5).lock();
+        writer.write_string("\nabc");
+        let row = writer.position().0;
+        assert_eq!(cursor::position(BUFFER_WIDTH), (row, 3));
+        writer.write_byte(b'd');
+        assert_eq!(cursor::position(BUFFER_WIDTH), (row, 4));
+        writer.write_byte(b'\n');
+        assert_eq!(cursor::position(BUFFER_WIDTH), writer.position());
+        drop(writer);
+        switch_to(original);
+    });
+}
+
+#[test_case]
+fn test_cursor_shape_and_visibility() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let original = active_console();
+        switch_to(5);
+        let mut writer = console(5).lock();
+        writer.enable_cursor(CursorShape::Block);
+        assert!(cursor::is_enabled());
+        assert_eq!(cursor::shape(), (0, 15));
+
+        writer.disable_cursor();
+        assert!(!cursor::is_enabled());
+
+        writer.enable_cursor(CursorShape::Underline);
+        assert!(cursor::is_enabled());
+        assert_eq!(cursor::shape(), (14, 15));
+        drop(writer);
+        switch_to(original);
+    });
+}
+
+#[cfg(test)]
+fn read_row(writer: &Writer, row: usize) -> [u8; BUFFER_WIDTH] {
+    let mut text = [0; BUFFER_WIDTH];
+    for (col, character) in text.iter_mut().enumerate() {
+        *character = writer.displayed_line(row)[col].ascii_character;
+    }
+    text
+}
+
+#[test_case]
+fn test_write_at_position() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.set_position(5, 10);
+        writer.write_string("hello");
+        assert_eq!(&read_row(&writer, 5)[10..15], b"hello");
+        assert_eq!(writer.position(), (5, 15));
+
+        writer.clear_to_end_of_line();
+        writer.set_position(5, 12);
+        writer.clear_to_end_of_line();
+        assert_eq!(&read_row(&writer, 5)[10..15], b"he   ");
+
+        // Writing somewhere else leaves the position alone
+        writer.write_string_at(0, 70, "status");
+        assert_eq!(&read_row(&writer, 0)[70..76], b"status");
+        assert_eq!(writer.position(), (5, 12));
+
+        // Out of range positions are clamped to the screen
+        writer.set_position(100, 100);
+        assert_eq!(writer.position(), (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1));
+        writer.clear_screen();
+    });
+}
+
+#[test_case]
+fn test_fills_from_top_then_scrolls() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        assert_eq!(writer.position(), (0, 0));
+        writer.write_string("first\nsecond");
+        assert_eq!(writer.position(), (1, 6));
+        assert_eq!(&read_row(&writer, 0)[..5], b"first");
+
+        // Once the last row is reached the screen scrolls instead
+        for _ in 0..BUFFER_HEIGHT {
+            writer.write_string("\n");
+        }
+        writer.write_string("last");
+        assert_eq!(writer.position(), (BUFFER_HEIGHT - 1, 4));
+        assert_eq!(&read_row(&writer, BUFFER_HEIGHT - 1)[..4], b"last");
+        writer.clear_screen();
+    });
+}
+
+#[test_case]
+fn test_start_row_keeps_rows_above() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.set_start_row(0);
+        writer.write_string("status bar");
+        writer.set_start_row(1);
+        assert_eq!(writer.position(), (1, 0));
+
+        for _ in 0..BUFFER_HEIGHT * 2 {
+            writer.write_string("scrolling\n");
+        }
+        assert_eq!(&read_row(&writer, 0)[..10], b"status bar");
+
+        // Without row tracking every new line scrolls
+        writer.set_row_tracking(false);
+        writer.set_position(BUFFER_HEIGHT - 1, 0);
+        writer.write_string("pinned\n");
+        assert_eq!(writer.position(), (BUFFER_HEIGHT - 1, 0));
+        assert_eq!(&read_row(&writer, BUFFER_HEIGHT - 2)[..6], b"pinned");
+
+        // Status bar writes are clipped to their row instead of wrapping or scrolling into it
+        let history = writer.scrollback().len();
+        let below = read_row(&writer, 1);
+        writer.write_string_at(0, 75, "\x1b[31moverflowing\nnext\x1b[2J\x0c");
+        assert_eq!(&read_row(&writer, 0)[..10], b"status bar");
+        assert_eq!(&read_row(&writer, 0)[75..], b"overf");
+        assert_eq!(writer.screen[0][75].color_code, ColorCode::new(Color::Red, Color::Black));
+        assert_eq!(read_row(&writer, 1), below);
+        assert_eq!(writer.scrollback().len(), history);
+        assert_eq!(writer.position(), (BUFFER_HEIGHT - 1, 0));
+
+        writer.reset_color();
+        writer.set_row_tracking(true);
+        writer.set_start_row(0);
+    });
+}
+
+#[test_case]
+fn test_ansi_colors() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.write_string("\x1b[31mr\x1b[1mR\x1b[44mb\x1b[0md");
+
+        let color_at = |writer: &Writer, col: usize| writer.screen[0][col].color_code;
+        assert_eq!(color_at(&writer, 0), ColorCode::new(Color::Red, Color::Black));
+        // Bold shows as bright
+        assert_eq!(color_at(&writer, 1), ColorCode::new(Color::LightRed, Color::Black));
+        assert_eq!(color_at(&writer, 2), ColorCode::new(Color::LightRed, Color::Blue));
+        assert_eq!(color_at(&writer, 3), ColorCode::new(Color::Yellow, Color::Black));
+        // The escape sequences themselves take up no space
+        assert_eq!(&read_row(&writer, 0)[..4], b"rRbd");
+        writer.clear_screen();
+    });
+}
+
+#[test_case]
+fn test_ansi_cursor_movement_and_erase() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.write_string("\x1b[3;10Hx");
+        assert_eq!(writer.position(), (2, 10));
+        assert_eq!(read_row(&writer, 2)[9], b'x');
+
+        writer.write_string("\x1b[2A\x1b[5D");
+        assert_eq!(writer.position(), (0, 5));
+        writer.write_string("\x1b[s\x1b[20;1H\x1b[u");
+        assert_eq!(writer.position(), (0, 5));
+
+        writer.write_string("abcdef\x1b[3D\x1b[K");
+        assert_eq!(&read_row(&writer, 0)[5..11], b"abc   ");
+        writer.write_string("\x1b[2J");
+        assert_eq!(read_row(&writer, 2)[9], b' ');
+        assert_eq!(writer.position(), (0, 8));
+        writer.clear_screen();
+    });
+}
+
+#[test_case]
+fn test_unicode_is_shown_in_cp437() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        // One glyph per character, and one replacement for each character the font lacks
+        writer.write_string("┌─é─┐ π≤3 😀ž!");
+        assert_eq!(&read_row(&writer, 0)[..13], b"\xDA\xC4\x82\xC4\xBF \xE3\xF33 \xFE\xFE!");
+        assert_eq!(writer.position(), (0, 13));
+
+        // Split across writes, the way a byte stream can arrive
+        let bytes = "ü".as_bytes();
+        writer.write_bytes(&bytes[..1]);
+        writer.write_bytes(&bytes[1..]);
+        assert_eq!(read_row(&writer, 0)[13], 0x81);
+        writer.clear_screen();
+    });
+}
+
+#[test_case]
+fn test_scrollback_view() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.scrollback.clear();
+        // 5 lines more than fit, the first 5 end up in history
+        for line in 0..BUFFER_HEIGHT + 5 {
+            write!(writer, "\nline {:02}", line).unwrap();
+        }
+        assert_eq!(writer.scrollback().len(), 6);
+        assert_eq!(&read_row(&writer, 0)[..7], b"line 05");
+
+        writer.scroll_back(2);
+        assert_eq!(&read_row(&writer, 0)[..7], b"line 03");
+        assert_eq!(&read_row(&writer, 2)[..7], b"line 05");
+        // Can't scroll past the oldest line
+        writer.page_up();
+        assert_eq!(writer.view_offset(), 6);
+        assert_eq!(read_row(&writer, 0)[0], b' ');
+        assert_eq!(&read_row(&writer, 1)[..7], b"line 00");
+
+        writer.page_down();
+        assert_eq!(writer.view_offset(), 0);
+        assert_eq!(&read_row(&writer, BUFFER_HEIGHT - 1)[..7], b"line 29");
+
+        // Writing while scrolled back snaps back to live output first
+        writer.scroll_back(3);
+        writer.write_string("!");
+        assert_eq!(writer.view_offset(), 0);
+        assert_eq!(&read_row(&writer, BUFFER_HEIGHT - 1)[..8], b"line 29!");
+        writer.clear_screen();
+    });
+}
+
+#[test_case]
+fn test_switching_consoles() {
+    use x86_64::instructions::interrupts;
+
+    let hardware_row = |row: usize| {
+        let mut text = [0; BUFFER_WIDTH];
+        for (col, character) in text.iter_mut().enumerate() {
+            *character = hardware().chars[row][col].read().ascii_character;
+        }
+        text
+    };
+
+    interrupts::without_interrupts(|| {
+        let original = active_console();
+        switch_to(3);
+        {
+            let mut writer = console(4).lock();
+            writer.clear_screen();
+            writer.write_string("in the background");
+            assert!(!writer.is_active());
+        }
+        {
+            let mut writer = console(3).lock();
+            writer.clear_screen();
+            writer.write_string("on screen");
+            writer.disable_cursor();
+        }
+        assert_eq!(&hardware_row(0)[..9], b"on screen");
+
+        // The background console kept its output and shows it once switched to
+        switch_to(4);
+        assert_eq!(active_console(), 4);
+        assert_eq!(&hardware_row(0)[..17], b"in the background");
+        assert!(cursor::is_enabled());
+        assert_eq!(cursor::position(BUFFER_WIDTH), (0, 17));
+
+        switch_to(3);
+        assert_eq!(&hardware_row(0)[..9], b"on screen");
+        assert!(!cursor::is_enabled());
+        console(3).lock().enable_cursor(CursorShape::Underline);
+        switch_to(original);
+    });
+}
+
+#[test_case]
+fn test_manual_flush() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let original = active_console();
+        switch_to(5);
+        let mut writer = console(5).lock();
+        writer.clear_screen();
+        writer.set_flush_policy(FlushPolicy::Manual);
+        writer.write_string("held back");
+        assert_eq!(hardware().chars[0][0].read().ascii_character, b' ');
+        assert_eq!(read_row(&writer, 0)[0], b'h');
+
+        writer.flush();
+        assert_eq!(hardware().chars[0][8].read().ascii_character, b'k');
+        assert!(writer.dirty.iter().all(DirtySpan::is_clean));
+
+        writer.set_flush_policy(FlushPolicy::EveryWrite);
+        writer.clear_screen();
+        drop(writer);
+        switch_to(original);
+    });
+}
+
+/* The writer as it was before consoles drew into RAM
+
+    Every character is written straight to the VGA buffer and every new
+    line moves the whole screen up through MMIO, reading each cell back.
+    Only kept as the baseline for the benchmark below.
+*/
+#[cfg(test)]
+struct DirectWriter {
+    column_position: usize,
+    color_code: ColorCode,
+}
+
+#[cfg(test)]
+impl DirectWriter {
+    fn new_line(&mut self) {
+        let buffer = hardware();
+        for row in 1..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let character = buffer.chars[row][col].read();
+                buffer.chars[row - 1][col].write(character);
+            }
+        }
+        let blank = ScreenChar { ascii_character: b' ', color_code: self.color_code };
+        for col in 0..BUFFER_WIDTH {
+            buffer.chars[BUFFER_HEIGHT - 1][col].write(blank);
+        }
+        self.column_position = 0;
+    }
+}
+
+#[cfg(test)]
+impl fmt::Write for DirectWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            match byte {
+                b'\n' => self.new_line(),
+                byte => {
+                    if self.column_position >= BUFFER_WIDTH {
+                        self.new_line();
+                    }
+                    let character = ScreenChar { ascii_character: byte, color_code: self.color_code };
+                    hardware().chars[BUFFER_HEIGHT - 1][self.column_position].write(character);
+                    self.column_position += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/* Writes the same output directly and with each flush policy
+
+    Only reports how many cycles each one took. Timings depend on the
+    host and on whether QEMU emulates the CPU, so nothing is asserted.
+*/
+#[test_case]
+fn test_flush_benchmark() {
+    use core::arch::x86_64::_rdtsc;
     use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
-}
\ No newline at end of file
+    use x86_64::instructions::interrupts;
+
+    const LINES: usize = BUFFER_HEIGHT * 8;
+    let mut direct = 0;
+    let mut cycles = [0; 3];
+    let policies = [FlushPolicy::WriteThrough, FlushPolicy::EveryWrite, FlushPolicy::Manual];
+
+    interrupts::without_interrupts(|| {
+        let original = active_console();
+        switch_to(5);
+        let mut writer = console(5).lock();
+
+        let mut direct_writer = DirectWriter { column_position: 0, color_code: writer.color_code() };
+        let start = unsafe { _rdtsc() };
+        for line in 0..LINES {
+            writeln!(direct_writer, "benchmark line {}", line).unwrap();
+        }
+        direct = unsafe { _rdtsc() }.wrapping_sub(start);
+
+        for (policy, cycles) in policies.iter().zip(cycles.iter_mut()) {
+            writer.clear_screen();
+            writer.set_flush_policy(*policy);
+            let start = unsafe { _rdtsc() };
+            for line in 0..LINES {
+                writeln!(writer, "benchmark line {}", line).unwrap();
+            }
+            writer.flush();
+            *cycles = unsafe { _rdtsc() }.wrapping_sub(start);
+        }
+        writer.set_flush_policy(FlushPolicy::EveryWrite);
+        writer.clear_screen();
+        drop(writer);
+        // Also redraws whatever the direct writer left on screen
+        switch_to(original);
+    });
+
+    crate::test_print!(
+        "direct {} / write through {} / every write {} / manual {} cycles ",
+        direct, cycles[0], cycles[1], cycles[2],
+    );
+}
+
+#[test_case]
+fn test_control_characters() {
+    use core::sync::atomic::AtomicUsize;
+    use x86_64::instructions::interrupts;
+
+    static BELLS: AtomicUsize = AtomicUsize::new(0);
+    fn count_bell() {
+        BELLS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.write_string("a\tb\r\tc");
+        // The second tab lands on the same stop, so c replaces b
+        assert_eq!(&read_row(&writer, 0)[..10], b"a       c ");
+        assert_eq!(writer.position(), (0, 9));
+        writer.set_tab_width(4);
+        writer.write_string("\td");
+        assert_eq!(writer.position(), (0, 13));
+        writer.set_tab_width(DEFAULT_TAB_WIDTH);
+
+        // Non-destructive backspace only moves, then overwriting replaces the character
+        writer.write_string("xy\x08z");
+        assert_eq!(&read_row(&writer, 0)[13..15], b"xz");
+        writer.set_destructive_backspace(true);
+        writer.write_string("\x08\x08");
+        assert_eq!(&read_row(&writer, 0)[12..15], b"d  ");
+
+        // From the first column backspace goes back to the end of the line above
+        writer.write_string("\n\x08");
+        assert_eq!(writer.position(), (0, BUFFER_WIDTH - 1));
+        writer.set_destructive_backspace(false);
+
+        set_bell_handler(Some(count_bell));
+        writer.write_string("\x07");
+        writer.write_byte(0x07);
+        assert_eq!(BELLS.load(Ordering::SeqCst), 2);
+        set_bell_handler(Some(crate::pit::bell));
+
+        writer.write_string("\x0c");
+        assert_eq!(writer.position(), (0, 0));
+        assert_eq!(read_row(&writer, 0)[0], b' ');
+    });
+}
+
+#[test_case]
+fn test_color_api() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.set_color(Color::White, Color::Blue);
+        assert_eq!(writer.color_code(), ColorCode::new(Color::White, Color::Blue));
+        writer.set_blink(true);
+        assert_eq!(writer.color_code(), ColorCode::new(Color::White, Color::Blue).with_blink(true));
+        writer.set_foreground(Color::Green);
+        assert_eq!(writer.color_code().foreground(), Color::Green);
+        writer.set_blink(false);
+
+        // Pops restore colors in reverse order, even past the stack depth
+        writer.push_color(Color::Red, Color::Black);
+        for _ in 0..COLOR_STACK_DEPTH + 2 {
+            writer.push_color(Color::Pink, Color::Black);
+        }
+        for _ in 0..COLOR_STACK_DEPTH + 2 {
+            assert!(writer.pop_color());
+        }
+        assert_eq!(writer.foreground(), Color::Red);
+        assert!(writer.pop_color());
+        assert_eq!(writer.color_code(), ColorCode::new(Color::Green, Color::Blue));
+        assert!(!writer.pop_color());
+
+        writer.reset_color();
+        assert_eq!(writer.color_code(), ColorCode::new(Color::Yellow, Color::Black));
+    });
+
+    print_colored!(Color::LightRed, "e");
+    {
+        let _guard = ColorGuard::new(LOG_CONSOLE, Color::LightGreen, Color::Black);
+        print!("o");
+    }
+    print!("d");
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color_at = |writer: &Writer, col: usize| writer.screen[0][col].color_code;
+        assert_eq!(&read_row(&writer, 0)[..3], b"eod");
+        assert_eq!(color_at(&writer, 0), ColorCode::new(Color::LightRed, Color::Black));
+        assert_eq!(color_at(&writer, 1), ColorCode::new(Color::LightGreen, Color::Black));
+        assert_eq!(color_at(&writer, 2), ColorCode::new(Color::Yellow, Color::Black));
+        writer.clear_screen();
+    });
+}
+
+#[test_case]
+fn test_blink_and_bright_backgrounds() {
+    use x86_64::instructions::interrupts;
+
+    set_blink_enabled(false);
+    assert!(!attribute::is_blink_enabled());
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.write_string("\x1b[104m");
+        assert_eq!(writer.color_code(), ColorCode::new(Color::Yellow, Color::LightBlue));
+        // Blinking can't show without blink mode
+        writer.set_blink(true);
+        assert_eq!(writer.color_code(), ColorCode::new(Color::Yellow, Color::LightBlue));
+    });
+
+    // Back in blink mode the same attributes lose the bright background and blink instead
+    set_blink_enabled(true);
+    assert!(attribute::is_blink_enabled());
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        assert_eq!(writer.color_code(), ColorCode::new(Color::Yellow, Color::Blue).with_blink(true));
+        writer.reset_color();
+    });
+}
@@ -0,0 +1,225 @@
+// ansi.rs
+
+/* ANSI / VT100 escape sequences
+
+    Escape sequences start with ESC (0x1B). The ones we care about are
+    Control Sequence Introducer (CSI) sequences, `ESC [`, followed by
+    numeric parameters separated by `;` and a final byte that says what
+    to do, e.g. `ESC [ 1 ; 3 1 m` sets bold red text.
+
+    The parser works on characters that have already been decoded from
+    UTF-8 and only splits them into actions, the writer decides what
+    they mean on screen.
+*/
+pub const ESCAPE: char = '\u{1B}';
+pub const MAX_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Print(char),
+    // C0 and C1 control characters and DEL
+    Control(char),
+    // ESC 7 and ESC 8
+    SaveCursor,
+    RestoreCursor,
+    // ESC c
+    Reset,
+    Csi(CsiSequence),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsiSequence {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    // Set by a `?` after the `[`, used by DEC private modes like cursor visibility
+    pub private: bool,
+    pub final_byte: u8,
+}
+
+impl CsiSequence {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    // Missing and zero parameters both mean "use the default"
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+pub struct Parser {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    private: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            len: 0,
+            private: false,
+        }
+    }
+
+    pub fn advance(&mut self, character: char) -> Option<Action> {
+        match self.state {
+            State::Ground => match character {
+                ESCAPE => {
+                    self.state = State::Escape;
+                    None
+                }
+                '\u{00}'..='\u{1F}' | '\u{7F}'..='\u{9F}' => Some(Action::Control(character)),
+                _ => Some(Action::Print(character)),
+            },
+            State::Escape => {
+                self.state = State::Ground;
+                match character {
+                    '[' => {
+                        self.state = State::Csi;
+                        self.params = [0; MAX_PARAMS];
+                        self.len = 0;
+                        self.private = false;
+                        None
+                    }
+                    '7' => Some(Action::SaveCursor),
+                    '8' => Some(Action::RestoreCursor),
+                    'c' => Some(Action::Reset),
+                    ESCAPE => {
+                        self.state = State::Escape;
+                        None
+                    }
+                    // Anything else isn't supported and is dropped
+                    _ => None,
+                }
+            }
+            State::Csi => self.advance_csi(character),
+        }
+    }
+
+    fn advance_csi(&mut self, character: char) -> Option<Action> {
+        match character {
+            '0'..='9' => {
+                if self.len == 0 {
+                    self.len = 1;
+                }
+                // Extra parameters are ignored rather than overflowing
+                if self.len <= MAX_PARAMS {
+                    let param = &mut self.params[self.len - 1];
+                    *param = param.saturating_mul(10).saturating_add(character as u16 - '0' as u16);
+                }
+                None
+            }
+            ';' => {
+                // An empty first parameter still counts
+                if self.len == 0 {
+                    self.len = 1;
+                }
+                self.len += 1;
+                None
+            }
+            '?' => {
+                self.private = true;
+                None
+            }
+            // Intermediate bytes, no sequence we handle uses them
+            ' '..='/' | '<'..='>' => None,
+            '@'..='~' => {
+                self.state = State::Ground;
+                Some(Action::Csi(CsiSequence {
+                    params: self.params,
+                    len: self.len.min(MAX_PARAMS),
+                    private: self.private,
+                    final_byte: character as u8,
+                }))
+            }
+            ESCAPE => {
+                self.state = State::Escape;
+                None
+            }
+            // Control characters are still carried out in the middle of a sequence
+            '\u{00}'..='\u{1F}' => Some(Action::Control(character)),
+            _ => {
+                self.state = State::Ground;
+                None
+            }
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+#[cfg(test)]
+fn parse_last(text: &str) -> Option<Action> {
+    let mut parser = Parser::new();
+    let mut last = None;
+    for character in text.chars() {
+        if let Some(action) = parser.advance(character) {
+            last = Some(action);
+        }
+    }
+    last
+}
+
+#[test_case]
+fn test_plain_text_and_controls() {
+    let mut parser = Parser::new();
+    assert_eq!(parser.advance('a'), Some(Action::Print('a')));
+    assert_eq!(parser.advance('\n'), Some(Action::Control('\n')));
+    assert_eq!(parser.advance('é'), Some(Action::Print('é')));
+}
+
+#[test_case]
+fn test_csi_parameters() {
+    let action = parse_last("\x1b[1;31m");
+    match action {
+        Some(Action::Csi(csi)) => {
+            assert_eq!(csi.final_byte, b'm');
+            assert_eq!(csi.params(), &[1, 31]);
+            assert!(!csi.private);
+        }
+        _ => panic!("expected a CSI sequence, got {:?}", action),
+    }
+
+    // Missing parameters fall back to their defaults
+    match parse_last("\x1b[;5H") {
+        Some(Action::Csi(csi)) => {
+            assert_eq!(csi.param(0, 1), 1);
+            assert_eq!(csi.param(1, 1), 5);
+            assert_eq!(csi.param(2, 1), 1);
+        }
+        other => panic!("expected a CSI sequence, got {:?}", other),
+    }
+}
+
+#[test_case]
+fn test_private_and_escape_sequences() {
+    match parse_last("\x1b[?25l") {
+        Some(Action::Csi(csi)) => assert!(csi.private && csi.params() == [25] && csi.final_byte == b'l'),
+        other => panic!("expected a CSI sequence, got {:?}", other),
+    }
+    assert_eq!(parse_last("\x1b7"), Some(Action::SaveCursor));
+    assert_eq!(parse_last("\x1b8"), Some(Action::RestoreCursor));
+
+    // Unsupported escapes are swallowed and text after them is printed normally
+    let mut parser = Parser::new();
+    assert_eq!(parser.advance(ESCAPE), None);
+    assert_eq!(parser.advance('Z'), None);
+    assert_eq!(parser.advance('x'), Some(Action::Print('x')));
+}
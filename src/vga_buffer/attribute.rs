@@ -0,0 +1,44 @@
+// attribute.rs
+use x86_64::instructions::port::Port;
+
+/* VGA attribute controller
+
+    Unlike the CRT controller, index and data share one port. A flip-flop
+    decides whether the next write to it is an index or data, and reading
+    the input status register resets it to index. Bit 5 of the index
+    must stay set, otherwise the controller stops showing the screen.
+
+    Register | Value
+    0x10     | Attribute mode control (bit 3 makes bit 7 of a color byte
+             | blink instead of brightening the background)
+*/
+const INPUT_STATUS_PORT: u16 = 0x3DA;
+const ADDRESS_DATA_PORT: u16 = 0x3C0;
+const DATA_READ_PORT: u16 = 0x3C1;
+
+const PALETTE_ADDRESS_SOURCE: u8 = 1 << 5;
+const MODE_CONTROL: u8 = 0x10;
+const BLINK_ENABLE: u8 = 1 << 3;
+
+// Leaves the flip-flop expecting data, so the register can be written straight after
+fn read_mode_control() -> u8 {
+    let mut status: Port<u8> = Port::new(INPUT_STATUS_PORT);
+    let mut address: Port<u8> = Port::new(ADDRESS_DATA_PORT);
+    let mut data: Port<u8> = Port::new(DATA_READ_PORT);
+    unsafe {
+        status.read();
+        address.write(MODE_CONTROL | PALETTE_ADDRESS_SOURCE);
+        data.read()
+    }
+}
+
+pub fn set_blink(enabled: bool) {
+    let mut address: Port<u8> = Port::new(ADDRESS_DATA_PORT);
+    let value = read_mode_control();
+    let value = if enabled { value | BLINK_ENABLE } else { value & !BLINK_ENABLE };
+    unsafe { address.write(value) };
+}
+
+pub fn is_blink_enabled() -> bool {
+    read_mode_control() & BLINK_ENABLE != 0
+}
@@ -0,0 +1,92 @@
+// cp437.rs
+
+/* Code page 437
+
+    The VGA text mode font is the original IBM PC character set. The
+    bottom half is ASCII (with glyphs instead of control characters) and
+    the top half holds accented Latin letters, box drawing, Greek and a
+    few math symbols. Unicode characters are looked up in these tables
+    to find the glyph that shows them.
+*/
+pub const REPLACEMENT_GLYPH: u8 = 0xFE; // ■
+
+// Glyphs for 0x01..=0x1F, which are control characters in ASCII
+const LOW_GLYPHS: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+const HOUSE_GLYPH: u8 = 0x7F; // ⌂
+
+// Glyphs for 0x80..=0xFF
+const HIGH_GLYPHS: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+// Characters that look close enough to a glyph meant for something else
+const ALIASES: [(char, u8); 8] = [
+    ('β', 0xE1),         // ß
+    ('\u{3BC}', 0xE6),   // µ, the Greek letter mu rather than the micro sign
+    ('\u{2126}', 0xEA),  // Ω, the ohm sign rather than omega
+    ('∑', 0xE4),         // Σ
+    ('∅', 0xED),         // φ
+    ('ϕ', 0xED),         // φ
+    ('∈', 0xEE),         // ε
+    ('∎', 0xFE),         // ■
+];
+
+// Returns None for characters the font can't show
+pub fn from_char(character: char) -> Option<u8> {
+    match character {
+        ' '..='~' => Some(character as u8),
+        '⌂' => Some(HOUSE_GLYPH),
+        _ => LOW_GLYPHS
+            .iter()
+            .position(|&glyph| glyph == character)
+            .map(|index| index as u8 + 0x01)
+            .or_else(|| {
+                HIGH_GLYPHS
+                    .iter()
+                    .position(|&glyph| glyph == character)
+                    .map(|index| index as u8 + 0x80)
+            })
+            .or_else(|| {
+                ALIASES
+                    .iter()
+                    .find(|&&(alias, _)| alias == character)
+                    .map(|&(_, glyph)| glyph)
+            }),
+    }
+}
+
+pub fn glyph(character: char) -> u8 {
+    from_char(character).unwrap_or(REPLACEMENT_GLYPH)
+}
+
+#[test_case]
+fn test_ascii_maps_to_itself() {
+    assert_eq!(from_char('A'), Some(b'A'));
+    assert_eq!(from_char('~'), Some(b'~'));
+}
+
+#[test_case]
+fn test_non_ascii_glyphs() {
+    assert_eq!(from_char('é'), Some(0x82));
+    assert_eq!(from_char('─'), Some(0xC4));
+    assert_eq!(from_char('╔'), Some(0xC9));
+    assert_eq!(from_char('π'), Some(0xE3));
+    assert_eq!(from_char('≤'), Some(0xF3));
+    assert_eq!(from_char('☺'), Some(0x01));
+    assert_eq!(from_char('β'), Some(0xE1));
+    assert_eq!(from_char('😀'), None);
+    assert_eq!(glyph('ž'), REPLACEMENT_GLYPH);
+    // Close lookalikes only, a euro sign shown as ε would change the meaning
+    assert_eq!(glyph('€'), REPLACEMENT_GLYPH);
+}
@@ -0,0 +1,94 @@
+// cursor.rs
+use x86_64::instructions::port::Port;
+
+/* VGA hardware cursor
+
+    The blinking cursor is drawn by the CRT controller, not by us. Its
+    registers are reached by writing the register index to the address
+    port and then reading or writing the data port.
+
+    Register | Value
+    0x0A     | Cursor start scanline (bit 5 disables the cursor)
+    0x0B     | Cursor end scanline
+    0x0E     | Cursor location high byte
+    0x0F     | Cursor location low byte
+*/
+const CRTC_ADDRESS_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+
+const CURSOR_START: u8 = 0x0A;
+const CURSOR_END: u8 = 0x0B;
+const LOCATION_HIGH: u8 = 0x0E;
+const LOCATION_LOW: u8 = 0x0F;
+
+const CURSOR_DISABLE: u8 = 1 << 5;
+
+// In 80x25 text mode every character cell is 16 scanlines high
+const LAST_SCANLINE: u8 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Underline,
+    Block,
+    // Scanlines counted from the top of the cell, both inclusive
+    Scanlines { start: u8, end: u8 },
+}
+
+impl CursorShape {
+    fn scanlines(self) -> (u8, u8) {
+        match self {
+            CursorShape::Underline => (LAST_SCANLINE - 1, LAST_SCANLINE),
+            CursorShape::Block => (0, LAST_SCANLINE),
+            CursorShape::Scanlines { start, end } => (start.min(LAST_SCANLINE), end.min(LAST_SCANLINE)),
+        }
+    }
+}
+
+fn read_register(index: u8) -> u8 {
+    let mut address: Port<u8> = Port::new(CRTC_ADDRESS_PORT);
+    let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        address.write(index);
+        data.read()
+    }
+}
+
+fn write_register(index: u8, value: u8) {
+    let mut address: Port<u8> = Port::new(CRTC_ADDRESS_PORT);
+    let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        address.write(index);
+        data.write(value);
+    }
+}
+
+// The top bits of both scanline registers are reserved and have to be kept
+pub fn enable(shape: CursorShape) {
+    let (start, end) = shape.scanlines();
+    write_register(CURSOR_START, (read_register(CURSOR_START) & 0xC0) | start);
+    write_register(CURSOR_END, (read_register(CURSOR_END) & 0xE0) | end);
+}
+
+pub fn disable() {
+    write_register(CURSOR_START, read_register(CURSOR_START) | CURSOR_DISABLE);
+}
+
+pub fn is_enabled() -> bool {
+    read_register(CURSOR_START) & CURSOR_DISABLE == 0
+}
+
+pub fn shape() -> (u8, u8) {
+    (read_register(CURSOR_START) & 0x1F, read_register(CURSOR_END) & 0x1F)
+}
+
+// The location is just the cell index, row * width + column
+pub fn set_position(row: usize, col: usize, width: usize) {
+    let location = (row * width + col) as u16;
+    write_register(LOCATION_LOW, location as u8);
+    write_register(LOCATION_HIGH, (location >> 8) as u8);
+}
+
+pub fn position(width: usize) -> (usize, usize) {
+    let location = (read_register(LOCATION_HIGH) as usize) << 8 | read_register(LOCATION_LOW) as usize;
+    (location / width, location % width)
+}
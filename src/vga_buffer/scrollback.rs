@@ -0,0 +1,115 @@
+// scrollback.rs
+use super::{ScreenChar, BUFFER_WIDTH};
+
+/* Scrollback history
+
+    Rows that scroll off the top of the screen are kept here, colors
+    included, so they can be shown again later. The lines live in a
+    ring buffer that overwrites the oldest line once the limit is
+    reached. The storage is handed in rather than allocated, so the
+    console keeps working before the heap exists or after it runs out.
+*/
+pub type Line = [ScreenChar; BUFFER_WIDTH];
+
+// Per console, so all of them together stay under half a megabyte
+pub const DEFAULT_CAPACITY: usize = 500;
+
+pub struct Scrollback {
+    lines: &'static mut [Line],
+    // Index of the oldest line
+    start: usize,
+    len: usize,
+    // Lines kept at most, never more than the storage holds
+    limit: usize,
+}
+
+impl Scrollback {
+    pub fn new(lines: &'static mut [Line]) -> Scrollback {
+        let limit = lines.len();
+        Scrollback { lines, start: 0, len: 0, limit }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Lowering the limit drops the oldest lines
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.min(self.capacity());
+        if self.len > self.limit {
+            self.start = (self.start + self.len - self.limit) % self.capacity();
+            self.len = self.limit;
+        }
+    }
+
+    pub fn push(&mut self, line: &Line) {
+        if self.limit == 0 {
+            return;
+        }
+
+        let index = (self.start + self.len) % self.capacity();
+        self.lines[index] = *line;
+        if self.len == self.limit {
+            self.start = (self.start + 1) % self.capacity();
+        } else {
+            self.len += 1;
+        }
+    }
+
+    // Line 0 is the oldest one
+    pub fn get(&self, index: usize) -> Option<&Line> {
+        if index < self.len {
+            Some(&self.lines[(self.start + index) % self.capacity()])
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+fn test_line(character: u8) -> Line {
+    use super::{Color, ColorCode};
+    [ScreenChar { ascii_character: character, color_code: ColorCode::new(Color::White, Color::Black) }; BUFFER_WIDTH]
+}
+
+#[test_case]
+fn test_ring_keeps_newest_lines() {
+    static mut LINES: [Line; 4] = [[super::BLANK; BUFFER_WIDTH]; 4];
+    let mut scrollback = Scrollback::new(unsafe { &mut *core::ptr::addr_of_mut!(LINES) });
+
+    for character in b'a'..=b'f' {
+        scrollback.push(&test_line(character));
+    }
+    assert_eq!(scrollback.len(), 4);
+    assert_eq!(scrollback.get(0).unwrap()[0].ascii_character, b'c');
+    assert_eq!(scrollback.get(3).unwrap()[0].ascii_character, b'f');
+    assert!(scrollback.get(4).is_none());
+
+    scrollback.set_limit(2);
+    assert_eq!(scrollback.len(), 2);
+    assert_eq!(scrollback.get(0).unwrap()[0].ascii_character, b'e');
+    scrollback.push(&test_line(b'g'));
+    assert_eq!(scrollback.get(0).unwrap()[0].ascii_character, b'f');
+    assert_eq!(scrollback.get(1).unwrap()[0].ascii_character, b'g');
+
+    scrollback.set_limit(0);
+    scrollback.push(&test_line(b'h'));
+    assert!(scrollback.is_empty());
+}
@@ -0,0 +1,111 @@
+// utf8.rs
+
+/* Streaming UTF-8 decoder
+
+    Characters can arrive split across writes, so the decoder keeps the
+    bytes of an unfinished character between calls.
+
+    Byte      | Meaning
+    0xxxxxxx  | ASCII
+    110xxxxx  | Start of a 2 byte character
+    1110xxxx  | Start of a 3 byte character
+    11110xxx  | Start of a 4 byte character
+    10xxxxxx  | Continuation byte
+
+    Invalid input (stray continuation bytes, truncated or overlong
+    sequences, surrogates) turns into one U+FFFD per broken sequence.
+*/
+pub const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
+
+pub struct Utf8Decoder {
+    code_point: u32,
+    // Continuation bytes still expected
+    remaining: u8,
+    // Smallest code point the current length may encode, anything below is overlong
+    minimum: u32,
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Utf8Decoder {
+        Utf8Decoder { code_point: 0, remaining: 0, minimum: 0 }
+    }
+
+    pub fn push<F>(&mut self, byte: u8, mut emit: F)
+    where
+        F: FnMut(char),
+    {
+        if self.remaining > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.code_point = (self.code_point << 6) | (byte & 0x3F) as u32;
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    let decoded = match core::char::from_u32(self.code_point) {
+                        Some(character) if self.code_point >= self.minimum => character,
+                        _ => REPLACEMENT_CHARACTER,
+                    };
+                    emit(decoded);
+                }
+                return;
+            }
+            // The sequence was cut short, the byte itself starts something new
+            self.remaining = 0;
+            emit(REPLACEMENT_CHARACTER);
+        }
+
+        match byte {
+            0x00..=0x7F => emit(byte as char),
+            0xC0..=0xDF => self.start(byte & 0x1F, 1, 0x80),
+            0xE0..=0xEF => self.start(byte & 0x0F, 2, 0x800),
+            0xF0..=0xF7 => self.start(byte & 0x07, 3, 0x10000),
+            _ => emit(REPLACEMENT_CHARACTER),
+        }
+    }
+
+    fn start(&mut self, bits: u8, remaining: u8, minimum: u32) {
+        self.code_point = bits as u32;
+        self.remaining = remaining;
+        self.minimum = minimum;
+    }
+}
+
+impl Default for Utf8Decoder {
+    fn default() -> Self {
+        Utf8Decoder::new()
+    }
+}
+
+#[cfg(test)]
+fn decode_into(decoder: &mut Utf8Decoder, bytes: &[u8], out: &mut [char; 8]) -> usize {
+    let mut len = 0;
+    for &byte in bytes {
+        decoder.push(byte, |character| {
+            out[len] = character;
+            len += 1;
+        });
+    }
+    len
+}
+
+#[test_case]
+fn test_decodes_multibyte_characters() {
+    let mut decoder = Utf8Decoder::new();
+    let mut out = ['\0'; 8];
+    let len = decode_into(&mut decoder, "aé─€😀".as_bytes(), &mut out);
+    assert_eq!(&out[..len], &['a', 'é', '─', '€', '😀']);
+
+    // A character split across two writes still comes out whole
+    let bytes = "ß".as_bytes();
+    assert_eq!(decode_into(&mut decoder, &bytes[..1], &mut out), 0);
+    assert_eq!(decode_into(&mut decoder, &bytes[1..], &mut out), 1);
+    assert_eq!(out[0], 'ß');
+}
+
+#[test_case]
+fn test_invalid_sequences_are_replaced() {
+    let mut decoder = Utf8Decoder::new();
+    let mut out = ['\0'; 8];
+
+    // Stray continuation byte, truncated sequence followed by ASCII, overlong '/'
+    let len = decode_into(&mut decoder, &[0x80, 0xE2, 0x94, b'x', 0xC0, 0xAF], &mut out);
+    assert_eq!(&out[..len], &[REPLACEMENT_CHARACTER, REPLACEMENT_CHARACTER, 'x', REPLACEMENT_CHARACTER]);
+}
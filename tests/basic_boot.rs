@@ -0,0 +1,29 @@
+// basic_boot.rs
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(monkos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+/* Runs tests straight after boot, before any of the kernel has been
+   initialised, to make sure the basics work without the setup in _start.
+*/
+use core::panic::PanicInfo;
+use monkos::println;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    test_main();
+
+    monkos::power::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    monkos::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_println() {
+    println!("test_println output");
+}
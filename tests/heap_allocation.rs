@@ -0,0 +1,96 @@
+// heap_allocation.rs
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(monkos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+/* Stress tests for the kernel heap
+
+   These run in their own kernel so the heap starts out empty and
+   nothing else has fragmented it yet.
+*/
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use monkos::allocator::HEAP_SIZE;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    monkos::init();
+    monkos::memory::init(boot_info);
+    test_main();
+
+    monkos::power::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    monkos::test_panic_handler(info)
+}
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn many_small_allocations() {
+    // Together these need far more than the heap, so freed memory has to be reused
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn large_allocations() {
+    // Bigger than any fixed size block, so they come from the fallback
+    for _ in 0..16 {
+        let buffer = alloc::vec![0xAAu8; HEAP_SIZE / 4];
+        assert!(buffer.iter().all(|&byte| byte == 0xAA));
+    }
+}
+
+// The bump allocator only reuses memory once everything has been freed
+#[cfg(not(feature = "bump_allocator"))]
+#[test_case]
+fn reuse_after_free_keeps_live_values() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn collections() {
+    let mut map = BTreeMap::new();
+    for i in 0..100 {
+        let mut name = String::from("key");
+        name.push(char::from(b'0' + (i % 10) as u8));
+        map.insert(i, name);
+    }
+    assert_eq!(map.len(), 100);
+    assert_eq!(map[&42], "key2");
+}
@@ -0,0 +1,33 @@
+// should_panic.rs
+#![no_std]
+#![no_main]
+
+/* A single test that passes by panicking. The panic handler is the
+   success path here, so there is no need for the test harness.
+*/
+use core::panic::PanicInfo;
+use monkos::{serial_print, serial_println};
+use monkos::power::hlt_loop;
+use monkos::qemu::{exit_qemu, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    should_fail();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+
+    hlt_loop()
+}
+
+fn should_fail() {
+    serial_print!("should_panic::should_fail...\t");
+    assert_eq!(0, 1);
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+
+    hlt_loop()
+}
@@ -0,0 +1,66 @@
+// stack_overflow.rs
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+/* This test boots as its own kernel so the overflow cannot take the
+   other tests down with it. It uses the kernel's GDT but installs a
+   test-only IDT whose double fault handler reports success.
+*/
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use monkos::{gdt, print, println, serial_print, serial_println};
+use monkos::qemu::{exit_qemu, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    print!("stack_overflow::stack_overflow...\t");
+    serial_print!("stack_overflow::stack_overflow...\t");
+
+    gdt::init();
+    init_test_idt();
+
+    stack_overflow();
+
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    // Prevent tail call optimisation turning the recursion into a loop
+    volatile::Volatile::new(0).read();
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    // Printing goes through vga_buffer::WRITER on the IST stack
+    println!("[ok]");
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    monkos::power::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    monkos::test_panic_handler(info)
+}